@@ -2,9 +2,9 @@
 use bevy::{
     math::vec3,
     prelude::*,
-    window::{CursorGrabMode, PrimaryWindow},
+    window::{CursorGrabMode, PrimaryWindow, WindowFocused},
 };
-use player::PlayerCursor;
+use player::{PlayerBuildAction, PlayerCursor};
 
 pub struct CameraPlugin;
 
@@ -13,7 +13,11 @@ fn build(&self, app: &mut App) {
         app.add_systems(
             Update,
             // Grab Cursor will likely need a software cursor, cuz the harware impl seems to not have a lot of parity
-            (update_pan_orbit_camera).run_if(any_with_component::<PanOrbitCamera>),
+            (
+                update_pan_orbit_camera,
+                update_camera_path.after(update_pan_orbit_camera),
+            )
+                .run_if(any_with_component::<PanOrbitCamera>),
         );
         app.add_plugins(InputContextPlugin::<CameraAction>::default());
         app.register_type::<PanOrbitCamera>();
@@ -30,8 +34,13 @@ pub enum CameraAction {
     Pan,
     #[actionlike(DualAxis)]
     Orbit,
+    #[actionlike(DualAxis)]
+    KeyboardOrbit,
     #[actionlike(Axis)]
     Zoom,
+    RecordPathPoint,
+    ClearPath,
+    TogglePathPlayback,
 }
 
 impl InputContextlike for CameraAction {
@@ -43,9 +52,20 @@ fn default_input_map() -> InputMap<Self> {
                 CameraAction::Orbit,
                 DualAxislikeChord::new(MouseButton::Right, MouseMove::default().inverted()),
             )
+            // Inverted to match dragging the mouse in the same direction
+            .with_dual_axis(
+                CameraAction::KeyboardOrbit,
+                VirtualDPad::arrow_keys().inverted(),
+            )
             // We use Digital to avoid inconsistencies between platform
             // On windows our pixel value is 1, but on web it is 100 (or 125 if you use Windows scaling)
             .with_axis(CameraAction::Zoom, MouseScrollAxis::Y.inverted().digital())
+            .with(CameraAction::RecordPathPoint, KeyCode::KeyB)
+            .with(
+                CameraAction::ClearPath,
+                ButtonlikeChord::modified(ModifierKey::Shift, KeyCode::KeyB),
+            )
+            .with(CameraAction::TogglePathPlayback, KeyCode::KeyP)
     }
     fn group_name() -> String {
         "Camera Actions".into()
@@ -53,7 +73,12 @@ fn group_name() -> String {
 }
 
 #[derive(Reflect, Component)]
-#[require(Camera3d, PanOrbitCameraSettings, InputContext<CameraAction>)]
+#[require(
+    Camera3d,
+    PanOrbitCameraSettings,
+    CameraPath,
+    InputContext<CameraAction>
+)]
 pub struct PanOrbitCamera {
     pub center: Vec3,
     pub velocity: Vec3,
@@ -79,6 +104,7 @@ fn default() -> Self {
                 DEFAULT_ZOOM,
                 default_settings.min_radius,
                 default_settings.max_radius,
+                default_settings.zoom_curve_power,
             ),
             pitch: -45.0_f32.to_radians(),
             yaw: 0.0,
@@ -86,6 +112,25 @@ fn default() -> Self {
     }
 }
 
+impl PanOrbitCamera {
+    /// Orbit around our center so the camera ends up at translation
+    pub fn orbit_to(&mut self, translation: Vec3, settings: &PanOrbitCameraSettings) {
+        let offset = translation - self.center;
+        let Some(direction) = offset.try_normalize() else {
+            return;
+        };
+
+        // Inverse of the rotation * Vec3::Z offset in update_pan_orbit_camera
+        self.yaw = direction.x.atan2(direction.z);
+        self.pitch = -direction.y.asin();
+        self.radius = offset.length();
+        let range = settings.max_radius - settings.min_radius;
+        self.zoom = ((self.radius - settings.min_radius) / range)
+            .clamp(0.0, 1.0)
+            .powf(settings.zoom_curve_power.recip());
+    }
+}
+
 #[derive(Reflect, Component)]
 pub struct PanOrbitCameraSettings {
     pub acceleration: f32,
@@ -94,9 +139,20 @@ pub struct PanOrbitCameraSettings {
     // The max speed we want when fully zoomed out
     pub max_speed: f32,
     pub orbit_sensitivity: f32,
+    // Speed of keyboard orbiting in mouse pixels per second, so it scales with orbit sensitivity
+    pub keyboard_orbit_speed: f32,
     pub zoom_sensitivity: f32,
     pub min_radius: f32,
     pub max_radius: f32,
+    // Exponent applied to zoom before mapping it to a radius, higher values give finer control when zoomed in
+    pub zoom_curve_power: f32,
+    // Optional (min, max) bounds the camera center is kept within
+    pub bounds: Option<(Vec3, Vec3)>,
+    pub edge_pan_enabled: bool,
+    // Distance in pixels from the window border at which we start edge panning
+    pub edge_pan_threshold: f32,
+    // Edge pan speed relative to the max speed
+    pub edge_pan_speed: f32,
 }
 
 impl Default for PanOrbitCameraSettings {
@@ -106,15 +162,153 @@ fn default() -> Self {
             max_speed_zoomed: 10.0,
             max_speed: 100.0,
             orbit_sensitivity: 0.01,
+            keyboard_orbit_speed: 150.0,
             zoom_sensitivity: 0.1,
             min_radius: 10.0,
             max_radius: 1000.0,
+            zoom_curve_power: 2.0,
+            bounds: None,
+            edge_pan_enabled: false,
+            edge_pan_threshold: 10.0,
+            edge_pan_speed: 1.0,
+        }
+    }
+}
+
+impl PanOrbitCameraSettings {
+    /// Keep a position within the bounds, min and max may be given in any order
+    pub fn clamp_to_bounds(&self, pos: Vec3) -> Vec3 {
+        match self.bounds {
+            Some((a, b)) => pos.clamp(a.min(b), a.max(b)),
+            None => pos,
+        }
+    }
+}
+
+/// Camera fly-through along recorded bookmarks, the camera moves along it at a constant speed
+#[derive(Component)]
+pub struct CameraPath {
+    bookmarks: Vec<CameraBookmark>,
+    curve: Option<CameraPathCurve>,
+    // Time in seconds it takes to travel the whole path
+    pub duration: f32,
+    // Point to look at while following the path, if None we look at the recorded centers
+    pub target: Option<Vec3>,
+    // Time since we started following the path, None when not playing
+    pub elapsed: Option<f32>,
+}
+
+impl Default for CameraPath {
+    fn default() -> Self {
+        CameraPath {
+            bookmarks: vec![],
+            curve: None,
+            duration: 10.0,
+            target: None,
+            elapsed: None,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct CameraBookmark {
+    pub translation: Vec3,
+    pub center: Vec3,
+}
+
+impl CameraPath {
+    pub fn bookmarks(&self) -> &[CameraBookmark] {
+        &self.bookmarks
+    }
+
+    pub fn add_bookmark(&mut self, bookmark: CameraBookmark) {
+        self.bookmarks.push(bookmark);
+        self.curve = CameraPathCurve::new(&self.bookmarks);
+    }
+
+    pub fn clear(&mut self) {
+        self.bookmarks.clear();
+        self.curve = None;
+        self.elapsed = None;
+    }
+
+    /// Fraction of the path we are at after playing for elapsed seconds, a path without duration is done at once
+    pub fn fraction(&self, elapsed: f32) -> f32 {
+        if self.duration > 0.0 {
+            elapsed / self.duration
+        } else {
+            1.0
+        }
+    }
+
+    /// Sample the camera transform and center at a fraction [0,1] of the path length
+    pub fn sample(&self, fraction: f32) -> Option<(Transform, Vec3)> {
+        let curve = self.curve.as_ref()?;
+        let t = curve.t_from_fraction(fraction);
+        let center = curve.center.position(t);
+        let transform = Transform::from_translation(curve.translation.position(t))
+            .looking_at(self.target.unwrap_or(center), Vec3::Y);
+        Some((transform, center))
+    }
+}
+
+const CAMERA_PATH_SAMPLES_PER_SEGMENT: usize = 32;
+
+struct CameraPathCurve {
+    translation: CubicCurve<Vec3>,
+    center: CubicCurve<Vec3>,
+    // Accumulated length at each sample, used to map distance to curve t
+    lengths: Vec<f32>,
+}
+
+impl CameraPathCurve {
+    fn new(bookmarks: &[CameraBookmark]) -> Option<Self> {
+        let curve = |points: Vec<Vec3>| CubicCardinalSpline::new_catmull_rom(points).to_curve();
+        let translation = curve(bookmarks.iter().map(|x| x.translation).collect()).ok()?;
+        let center = curve(bookmarks.iter().map(|x| x.center).collect()).ok()?;
+
+        let samples = translation.segments().len() * CAMERA_PATH_SAMPLES_PER_SEGMENT;
+        let mut lengths = Vec::with_capacity(samples + 1);
+        let mut length = 0.0;
+        let mut prev = translation.position(0.0);
+        lengths.push(length);
+        for i in 1..=samples {
+            let pos = translation.position(i as f32 / CAMERA_PATH_SAMPLES_PER_SEGMENT as f32);
+            length += pos.distance(prev);
+            prev = pos;
+            lengths.push(length);
+        }
+
+        Some(CameraPathCurve {
+            translation,
+            center,
+            lengths,
+        })
+    }
+
+    fn t_from_fraction(&self, fraction: f32) -> f32 {
+        let total = *self.lengths.last().unwrap();
+        // All bookmarks share a position, so there is no distance to travel and we are at the last one
+        if total <= 0.0 {
+            return (self.lengths.len() - 1) as f32 / CAMERA_PATH_SAMPLES_PER_SEGMENT as f32;
         }
+        let distance = fraction.clamp(0.0, 1.0) * total;
+        let idx = self
+            .lengths
+            .partition_point(|x| *x < distance)
+            .clamp(1, self.lengths.len() - 1);
+        let (start, end) = (self.lengths[idx - 1], self.lengths[idx]);
+        let alpha = if end > start {
+            (distance - start) / (end - start)
+        } else {
+            0.0
+        };
+        ((idx - 1) as f32 + alpha) / CAMERA_PATH_SAMPLES_PER_SEGMENT as f32
     }
 }
 
-fn calculate_desired_radius(zoom: f32, min_radius: f32, max_radius: f32) -> f32 {
-    min_radius.lerp(max_radius, zoom.powi(2))
+fn calculate_desired_radius(zoom: f32, min_radius: f32, max_radius: f32, power: f32) -> f32 {
+    min_radius.lerp(max_radius, zoom.powf(power))
 }
 
 fn update_pan_orbit_camera(
@@ -126,15 +320,28 @@ fn update_pan_orbit_camera(
         &mut PanOrbitCamera,
     )>,
     cursors: Query<&PlayerCursor>,
+    build_inputs: Query<&ActionState<PlayerBuildAction>>,
+    windows: Query<&Window, With<PrimaryWindow>>,
     time: Res<Time>,
+    mut ev_focused: EventReader<WindowFocused>,
     mut last_pan_offset: Local<Vec3>,
 ) {
     let player_cursor = cursors.single();
+    // Cursor position is stale on the frame we regain focus, so don't zoom towards it
+    let regained_focus = ev_focused.read().any(|ev| ev.focused);
+    // Scrolling is also used to adjust the build height, in which case we don't want to zoom
+    let adjusting_build_height = build_inputs
+        .iter()
+        .any(|input| input.value(&PlayerBuildAction::AdjustBuildHeight) != 0.0);
 
     q.iter_mut()
         .for_each(|(input, settings, mut t, mut state)| {
             // Calculate rotation
-            let direction = input.axis_pair(&CameraAction::Orbit) * settings.orbit_sensitivity;
+            let direction = (input.axis_pair(&CameraAction::Orbit)
+                + input.axis_pair(&CameraAction::KeyboardOrbit)
+                    * settings.keyboard_orbit_speed
+                    * time.delta_secs())
+                * settings.orbit_sensitivity;
             state.yaw += direction.x;
             state.pitch += direction.y;
             state.pitch = state
@@ -143,11 +350,17 @@ fn update_pan_orbit_camera(
             let rotation = Quat::from_euler(EulerRot::YXZ, state.yaw, state.pitch, 0.0);
 
             // Calculate radius
-            state.zoom += input.value(&CameraAction::Zoom) * settings.zoom_sensitivity;
+            if !adjusting_build_height {
+                state.zoom += input.value(&CameraAction::Zoom) * settings.zoom_sensitivity;
+            }
             state.zoom = state.zoom.clamp(0.0, 1.0);
 
-            let desired_radius =
-                calculate_desired_radius(state.zoom, settings.min_radius, settings.max_radius);
+            let desired_radius = calculate_desired_radius(
+                state.zoom,
+                settings.min_radius,
+                settings.max_radius,
+                settings.zoom_curve_power,
+            );
             const RADIUS_LERP_RATE: f32 = 50.0;
             let alpha = (time.delta_secs() * RADIUS_LERP_RATE).min(1.0);
 
@@ -157,13 +370,21 @@ fn update_pan_orbit_camera(
 
             // If we zoom with mkb we want to zoom towards cursor pos
             let mut center_zoom_offset = Vec3::ZERO;
-            if radius_delta != 0.0 {
+            if radius_delta != 0.0 && !regained_focus {
                 let norm_radius_delta = -radius_delta / (state.radius + settings.min_radius);
 
-                let mut center_to_cursor = player_cursor.world_pos - state.center;
+                // Without a cursor in the window we zoom towards the screen center, which is our center
+                let zoom_target = if player_cursor.screen_pos.is_some() {
+                    player_cursor.world_pos
+                } else {
+                    state.center
+                };
+                let mut center_to_cursor = zoom_target - state.center;
                 const MAX_CENTER_TO_CURSOR_LENGTH: f32 = 100.0;
                 center_to_cursor = center_to_cursor.clamp_length_max(MAX_CENTER_TO_CURSOR_LENGTH);
                 center_zoom_offset = center_to_cursor * norm_radius_delta;
+                center_zoom_offset =
+                    settings.clamp_to_bounds(state.center + center_zoom_offset) - state.center;
 
                 // gizmos.ray(state.center, center_to_cursor, RED);
                 // gizmos.sphere(cursor, Quat::IDENTITY, 10.0, RED);
@@ -174,10 +395,34 @@ fn update_pan_orbit_camera(
             let direction = input.axis_pair(&CameraAction::Translate);
             let forward = Quat::from_axis_angle(Vec3::Y, state.yaw);
             let direction = forward * vec3(direction.x, 0.0, direction.y);
-            let desired_velocity = direction.normalize_or_zero()
-                * settings
-                    .max_speed_zoomed
-                    .lerp(settings.max_speed, state.zoom);
+            let max_speed = settings
+                .max_speed_zoomed
+                .lerp(settings.max_speed, state.zoom);
+            let mut desired_velocity = direction.normalize_or_zero() * max_speed;
+
+            // Handle edge pan, middle mouse pan takes precedence
+            if settings.edge_pan_enabled && !input.pressed(&CameraAction::Pan) {
+                if let Some((pos, window)) = player_cursor.screen_pos.zip(windows.get_single().ok())
+                {
+                    let size = window.size();
+                    let mut edge = Vec2::ZERO;
+                    if pos.x < settings.edge_pan_threshold {
+                        edge.x -= 1.0;
+                    } else if pos.x > size.x - settings.edge_pan_threshold {
+                        edge.x += 1.0;
+                    }
+                    // Screen y goes down, while our forward goes up
+                    if pos.y < settings.edge_pan_threshold {
+                        edge.y -= 1.0;
+                    } else if pos.y > size.y - settings.edge_pan_threshold {
+                        edge.y += 1.0;
+                    }
+
+                    let edge = forward * vec3(edge.x, 0.0, edge.y);
+                    desired_velocity +=
+                        edge.normalize_or_zero() * max_speed * settings.edge_pan_speed;
+                }
+            }
 
             state.velocity = state
                 .velocity
@@ -199,6 +444,7 @@ fn update_pan_orbit_camera(
             }
             state.center =
                 state.center + state.velocity * time.delta_secs() + center_zoom_offset + pan;
+            state.center = settings.clamp_to_bounds(state.center);
 
             // Apply state to transform
             let offset = rotation * Vec3::Z * state.radius;
@@ -206,3 +452,157 @@ fn update_pan_orbit_camera(
             t.rotation = rotation;
         });
 }
+
+fn update_camera_path(
+    mut q: Query<(
+        &ActionState<CameraAction>,
+        &mut CameraPath,
+        &mut PanOrbitCamera,
+        &PanOrbitCameraSettings,
+        &mut Transform,
+    )>,
+    time: Res<Time>,
+) {
+    q.iter_mut()
+        .for_each(|(input, mut path, mut state, settings, mut t)| {
+            if input.just_pressed(&CameraAction::RecordPathPoint) {
+                path.add_bookmark(CameraBookmark {
+                    translation: t.translation,
+                    center: state.center,
+                });
+            }
+            if input.just_pressed(&CameraAction::ClearPath) {
+                path.clear();
+            }
+            // Fraction of the path we stopped playing at
+            let mut stopped_at = None;
+            if input.just_pressed(&CameraAction::TogglePathPlayback) {
+                if let Some(elapsed) = path.elapsed {
+                    stopped_at = Some(path.fraction(elapsed));
+                    path.elapsed = None;
+                } else if path.bookmarks.len() >= 2 {
+                    path.elapsed = Some(0.0);
+                }
+            }
+
+            if let Some(elapsed) = path.elapsed {
+                let elapsed = elapsed + time.delta_secs();
+                let fraction = path.fraction(elapsed);
+                if let Some((transform, center)) = path.sample(fraction) {
+                    *t = transform;
+                    state.center = center;
+                }
+                if fraction >= 1.0 {
+                    stopped_at = Some(1.0);
+                }
+                path.elapsed = (fraction < 1.0).then_some(elapsed);
+            }
+
+            // Hand the last pose back to the orbit state, otherwise we snap back to where we started playing
+            if let Some((transform, center)) = stopped_at.and_then(|x| path.sample(x)) {
+                *t = transform;
+                state.center = center;
+                state.orbit_to(transform.translation, settings);
+            }
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clamp_to_swapped_bounds() {
+        let settings = PanOrbitCameraSettings {
+            bounds: Some((Vec3::splat(10.0), Vec3::splat(-10.0))),
+            ..default()
+        };
+        assert_eq!(
+            settings.clamp_to_bounds(vec3(20.0, 0.0, -20.0)),
+            vec3(10.0, 0.0, -10.0)
+        );
+    }
+
+    #[test]
+    fn test_orbit_to_matches_orbit_offset() {
+        let settings = PanOrbitCameraSettings::default();
+        let expected = PanOrbitCamera {
+            center: vec3(10.0, 0.0, -5.0),
+            yaw: 1.0,
+            pitch: -0.6,
+            zoom: 0.3,
+            radius: calculate_desired_radius(
+                0.3,
+                settings.min_radius,
+                settings.max_radius,
+                settings.zoom_curve_power,
+            ),
+            ..default()
+        };
+        let rotation = Quat::from_euler(EulerRot::YXZ, expected.yaw, expected.pitch, 0.0);
+        let translation = expected.center + rotation * Vec3::Z * expected.radius;
+
+        let mut state = PanOrbitCamera {
+            center: expected.center,
+            ..default()
+        };
+        state.orbit_to(translation, &settings);
+
+        assert!((state.yaw - expected.yaw).abs() < 1e-4);
+        assert!((state.pitch - expected.pitch).abs() < 1e-4);
+        assert!((state.radius - expected.radius).abs() < 1e-2);
+        assert!((state.zoom - expected.zoom).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_desired_radius_continuity() {
+        let settings = PanOrbitCameraSettings::default();
+        let radius = |zoom| {
+            calculate_desired_radius(
+                zoom,
+                settings.min_radius,
+                settings.max_radius,
+                settings.zoom_curve_power,
+            )
+        };
+
+        assert_eq!(radius(0.0), settings.min_radius);
+        assert_eq!(radius(1.0), settings.max_radius);
+
+        // A single scroll step should never make the radius jump
+        const STEPS: usize = 1000;
+        let max_jump = (settings.max_radius - settings.min_radius) * 0.01;
+        let mut prev = radius(0.0);
+        for i in 1..=STEPS {
+            let current = radius(i as f32 / STEPS as f32);
+            assert!(current >= prev);
+            assert!(current - prev < max_jump);
+            prev = current;
+        }
+    }
+
+    #[test]
+    fn test_degenerate_camera_path_ends_at_last_bookmark() {
+        let mut path = CameraPath {
+            duration: 0.0,
+            ..default()
+        };
+        assert_eq!(path.fraction(0.0), 1.0);
+
+        // Same position but different centers, so only the translation has no length
+        let translation = vec3(0.0, 10.0, 10.0);
+        path.add_bookmark(CameraBookmark {
+            translation,
+            center: Vec3::ZERO,
+        });
+        path.add_bookmark(CameraBookmark {
+            translation,
+            center: Vec3::X,
+        });
+
+        let (transform, center) = path.sample(0.0).unwrap();
+        assert_eq!(transform.translation, translation);
+        assert!(transform.rotation.is_finite());
+        assert!(center.distance(Vec3::X) < 1e-4);
+    }
+}
@@ -1,22 +1,123 @@
+use bevy::diagnostic::{
+    DiagnosticsStore, EntityCountDiagnosticsPlugin, FrameTimeDiagnosticsPlugin,
+};
 use bevy::input::common_conditions::input_toggle_active;
+use bevy::picking::pointer::PointerInteraction;
 use bevy::prelude::*;
 use bevy::window::PrimaryWindow;
 use bevy_egui::*;
 use bevy_inspector_egui::*;
+use leafwing_input_manager::prelude::ActionState;
+
+use crate::game::building::rail::{get_closest_rail, Rail};
+use crate::game::feedback::FeedbackLog;
+use crate::game::player::{PlayerBuildAction, PlayerCursor, PlayerViewAction};
+use crate::game::world::FogSettings;
+use crate::game::NetOwner;
 
 pub struct DebugPlugin;
 
+/// Toggles for our debug gizmo systems
+#[derive(Resource, Reflect, Clone)]
+#[reflect(Resource)]
+pub struct DebugGizmoFlags {
+    pub rail_path: bool,
+    pub rail_graph: bool,
+    // Highlight all rails connected to the hovered joint
+    pub connected_rails: bool,
+    pub mesh_intersections: bool,
+    pub build_grid: bool,
+}
+
+impl Default for DebugGizmoFlags {
+    fn default() -> Self {
+        DebugGizmoFlags {
+            rail_path: true,
+            rail_graph: true,
+            connected_rails: true,
+            mesh_intersections: false,
+            build_grid: true,
+        }
+    }
+}
+
+/// Gizmos only developers care about, players see the default group
+#[derive(Default, Reflect, GizmoConfigGroup)]
+pub struct DebugGizmos;
+
+/// Hide the debug gizmos so the game is presentable without developer overlays
+#[derive(Resource, Reflect, Default)]
+#[reflect(Resource)]
+pub struct ReleaseVisuals(pub bool);
+
+/// Run condition for a debug gizmo system
+pub fn debug_gizmo_enabled(
+    flag: fn(&DebugGizmoFlags) -> bool,
+) -> impl FnMut(Res<DebugGizmoFlags>, Res<ReleaseVisuals>) -> bool {
+    move |flags: Res<DebugGizmoFlags>, release: Res<ReleaseVisuals>| !release.0 && flag(&flags)
+}
+
+/// Resources the debug gizmo run conditions depend on, also used without the DebugPlugin in headless apps
+pub fn debug_gizmo_plugin(app: &mut App) {
+    app.init_gizmo_group::<DebugGizmos>();
+    app.init_resource::<DebugGizmoFlags>();
+    app.register_type::<DebugGizmoFlags>();
+    app.init_resource::<ReleaseVisuals>();
+    app.register_type::<ReleaseVisuals>();
+}
+
+// Also catches debug gizmos drawn outside of the gated debug systems
+fn apply_release_visuals(release: Res<ReleaseVisuals>, mut config: ResMut<GizmoConfigStore>) {
+    config.config_mut::<DebugGizmos>().0.enabled = !release.0;
+}
+
 impl Plugin for DebugPlugin {
     fn build(&self, app: &mut App) {
         app.add_plugins(EguiPlugin);
+        app.add_plugins(debug_gizmo_plugin);
         app.add_plugins(bevy_inspector_egui::DefaultInspectorConfigPlugin); // adds default options and `InspectorEguiImpl`s
+        app.add_plugins((FrameTimeDiagnosticsPlugin, EntityCountDiagnosticsPlugin));
         app.add_systems(
             Update,
-            world_inspector_ui.run_if(input_toggle_active(false, KeyCode::Delete)),
+            (
+                toggle_world_inspector,
+                sync_inspector_click_actions,
+                pick_inspected_entity.run_if(|inspector: Res<WorldInspector>| inspector.open),
+                world_inspector_ui.run_if(|inspector: Res<WorldInspector>| inspector.open),
+                feedback_log_ui.run_if(input_toggle_active(false, KeyCode::F2)),
+                performance_ui.run_if(input_toggle_active(false, KeyCode::F3)),
+                debug_gizmo_flags_ui.run_if(input_toggle_active(false, KeyCode::F4)),
+                visuals_ui.run_if(input_toggle_active(false, KeyCode::F7)),
+                apply_release_visuals.run_if(resource_changed::<ReleaseVisuals>),
+                // Keep tracking while hidden so we don't miss removed rails
+                update_rail_network_stats,
+                rail_network_stats_ui
+                    .after(update_rail_network_stats)
+                    .run_if(input_toggle_active(false, KeyCode::F6)),
+            ),
         );
+        app.init_resource::<RailNetworkStats>();
+        app.init_resource::<WorldInspector>();
     }
 }
 
+/// State of the world inspector, while open left clicking an entity selects it
+#[derive(Resource, Default)]
+pub struct WorldInspector {
+    pub open: bool,
+    pub selected: Option<Entity>,
+}
+
+/// Summary of the rail network, only recalculated when rails are added or removed
+#[derive(Resource, Default)]
+pub struct RailNetworkStats {
+    pub rails: usize,
+    pub total_length: f32,
+    pub longest_rail: f32,
+    // Joints that connect to more than one other rail
+    pub junctions: usize,
+}
+
 fn world_inspector_ui(world: &mut World) {
     const DEFAULT_SIZE: (f32, f32) = (320., 320.);
 
@@ -29,12 +130,235 @@ fn world_inspector_ui(world: &mut World) {
     };
     let mut egui_context = egui_context.clone();
 
+    let selected = world
+        .resource::<WorldInspector>()
+        .selected
+        .filter(|e| world.get_entity(*e).is_ok());
+
     egui::Window::new("World Inspector")
         .default_size(DEFAULT_SIZE)
         .show(egui_context.get_mut(), |ui| {
             egui::ScrollArea::both().show(ui, |ui| {
+                if let Some(selected) = selected {
+                    egui::CollapsingHeader::new(format!("Selected {selected}"))
+                        .default_open(true)
+                        .show(ui, |ui| {
+                            bevy_inspector::ui_for_entity(world, selected, ui);
+                        });
+                }
                 bevy_inspector::ui_for_world(world, ui);
                 ui.allocate_space(ui.available_size());
             });
         });
 }
+
+fn toggle_world_inspector(mut inspector: ResMut<WorldInspector>, keys: Res<ButtonInput<KeyCode>>) {
+    if keys.just_pressed(KeyCode::Delete) {
+        inspector.open = !inspector.open;
+    }
+}
+
+// Left click picks while the inspector is open, so it shouldn't also enter build mode or place rails
+fn sync_inspector_click_actions(
+    inspector: Res<WorldInspector>,
+    mut view: Query<&mut ActionState<PlayerViewAction>>,
+    mut build: Query<&mut ActionState<PlayerBuildAction>>,
+) {
+    let action = PlayerViewAction::EnterBuildMode;
+    view.iter_mut()
+        .filter(|state| state.action_disabled(&action) != inspector.open)
+        .for_each(|mut state| match inspector.open {
+            true => state.disable_action(&action),
+            false => state.enable_action(&action),
+        });
+
+    let action = PlayerBuildAction::Interact;
+    build
+        .iter_mut()
+        .filter(|state| state.action_disabled(&action) != inspector.open)
+        .for_each(|mut state| match inspector.open {
+            true => state.disable_action(&action),
+            false => state.enable_action(&action),
+        });
+}
+
+fn pick_inspected_entity(
+    mut inspector: ResMut<WorldInspector>,
+    mut contexts: EguiContexts,
+    mouse: Res<ButtonInput<MouseButton>>,
+    pointers: Query<&PointerInteraction>,
+    rails: Query<(Entity, &Rail)>,
+    cursor: Query<&PlayerCursor, With<NetOwner>>,
+) {
+    if !mouse.just_pressed(MouseButton::Left) || contexts.ctx_mut().wants_pointer_input() {
+        return;
+    }
+
+    // Rails have no meshes to hit, so we prefer a rail close to the cursor
+    const RAIL_PICK_DISTANCE: f32 = 2.0;
+    let rail = cursor.get_single().ok().and_then(|cursor| {
+        get_closest_rail(rails.iter(), cursor.world_pos)
+            .filter(|(_, distance)| *distance < RAIL_PICK_DISTANCE)
+            .map(|(e, _)| e)
+    });
+    let picked = rail.or_else(|| {
+        pointers
+            .iter()
+            .find_map(|interaction| interaction.get_nearest_hit())
+            .map(|(e, _)| *e)
+    });
+
+    if picked.is_some() {
+        inspector.selected = picked;
+    }
+}
+
+fn feedback_log_ui(mut contexts: EguiContexts, log: Res<FeedbackLog>) {
+    egui::Window::new("Feedback Log")
+        .default_size((320., 240.))
+        .show(contexts.ctx_mut(), |ui| {
+            egui::ScrollArea::vertical()
+                .stick_to_bottom(true)
+                .show(ui, |ui| {
+                    log.entries.iter().for_each(|entry| {
+                        let text = format!("[{:.2}] {}", entry.time, entry.event.message);
+                        if entry.event.is_error {
+                            ui.colored_label(egui::Color32::LIGHT_RED, text);
+                        } else {
+                            ui.label(text);
+                        }
+                    });
+                });
+        });
+}
+
+fn performance_ui(
+    mut contexts: EguiContexts,
+    diagnostics: Res<DiagnosticsStore>,
+    rails: Query<(), With<Rail>>,
+) {
+    let fps = diagnostics
+        .get(&FrameTimeDiagnosticsPlugin::FPS)
+        .and_then(|x| x.smoothed())
+        .unwrap_or_default();
+    let frame_time = diagnostics
+        .get(&FrameTimeDiagnosticsPlugin::FRAME_TIME)
+        .and_then(|x| x.smoothed())
+        .unwrap_or_default();
+    let entities = diagnostics
+        .get(&EntityCountDiagnosticsPlugin::ENTITY_COUNT)
+        .and_then(|x| x.value())
+        .unwrap_or_default();
+
+    egui::Window::new("Performance")
+        .anchor(egui::Align2::RIGHT_TOP, (-10., 10.))
+        .resizable(false)
+        .show(contexts.ctx_mut(), |ui| {
+            ui.label(format!("FPS: {:.0}", fps));
+            ui.label(format!("Frame time: {:.2} ms", frame_time));
+            ui.label(format!("Entities: {:.0}", entities));
+            ui.label(format!("Rails: {}", rails.iter().len()));
+        });
+}
+
+fn debug_gizmo_flags_ui(mut contexts: EguiContexts, mut flags: ResMut<DebugGizmoFlags>) {
+    egui::Window::new("Debug Gizmos")
+        .resizable(false)
+        .show(contexts.ctx_mut(), |ui| {
+            ui.checkbox(&mut flags.rail_path, "Rail path");
+            ui.checkbox(&mut flags.rail_graph, "Rail graph");
+            ui.checkbox(&mut flags.connected_rails, "Connected rails");
+            ui.checkbox(&mut flags.mesh_intersections, "Mesh intersections");
+            ui.checkbox(&mut flags.build_grid, "Build grid");
+        });
+}
+
+fn visuals_ui(
+    mut contexts: EguiContexts,
+    mut fog: ResMut<FogSettings>,
+    mut release: ResMut<ReleaseVisuals>,
+) {
+    egui::Window::new("Visuals")
+        .resizable(false)
+        .show(contexts.ctx_mut(), |ui| {
+            let mut hide_gizmos = release.0;
+            ui.checkbox(&mut hide_gizmos, "Release visuals (hide debug gizmos)");
+            if hide_gizmos != release.0 {
+                release.0 = hide_gizmos;
+            }
+
+            // Only write back on change so we don't trigger change detection every frame
+            let mut enabled = fog.enabled;
+            let mut visibility = fog.visibility;
+            ui.checkbox(&mut enabled, "Fog");
+            ui.add_enabled(
+                enabled,
+                egui::Slider::new(&mut visibility, 100.0..=20_000.0)
+                    .logarithmic(true)
+                    .text("Visibility"),
+            );
+            if enabled != fog.enabled || visibility != fog.visibility {
+                fog.enabled = enabled;
+                fog.visibility = visibility;
+            }
+        });
+}
+
+fn update_rail_network_stats(
+    mut stats: ResMut<RailNetworkStats>,
+    rails: Query<&Rail>,
+    added: Query<(), Added<Rail>>,
+    mut removed: RemovedComponents<Rail>,
+) {
+    let removed_any = removed.read().count() > 0;
+    if added.is_empty() && !removed_any {
+        return;
+    }
+
+    let lengths: Vec<f32> = rails.iter().map(|rail| rail.length()).collect();
+    *stats = RailNetworkStats {
+        rails: lengths.len(),
+        total_length: lengths.iter().sum(),
+        longest_rail: lengths.iter().copied().fold(0.0, f32::max),
+        junctions: rails
+            .iter()
+            .flat_map(|rail| rail.joints.iter())
+            .filter(|joint| joint.n_joints.iter().flatten().count() > 1)
+            .count(),
+    };
+}
+
+fn rail_network_stats_ui(mut contexts: EguiContexts, stats: Res<RailNetworkStats>) {
+    egui::Window::new("Rail Network")
+        .resizable(false)
+        .show(contexts.ctx_mut(), |ui| {
+            ui.label(format!("Rails: {}", stats.rails));
+            ui.label(format!("Total length: {:.1}", stats.total_length));
+            ui.label(format!("Longest rail: {:.1}", stats.longest_rail));
+            ui.label(format!("Junctions: {}", stats.junctions));
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::ecs::system::RunSystemOnce;
+
+    #[test]
+    fn test_toggle_debug_gizmo_flag() {
+        let mut app = crate::headless_test_app();
+        app.update();
+        let enabled = |app: &mut App| {
+            app.world_mut()
+                .run_system_once(debug_gizmo_enabled(|x| x.rail_path))
+                .unwrap()
+        };
+
+        assert!(enabled(&mut app));
+        app.world_mut().resource_mut::<DebugGizmoFlags>().rail_path = false;
+        assert!(!enabled(&mut app));
+        app.world_mut().resource_mut::<DebugGizmoFlags>().rail_path = true;
+        app.world_mut().resource_mut::<ReleaseVisuals>().0 = true;
+        assert!(!enabled(&mut app));
+    }
+}
@@ -9,17 +9,32 @@
 use std::f32::consts::PI;
 
 use bevy::color::palettes::tailwind::*;
+use bevy::input::mouse::AccumulatedMouseMotion;
 use bevy::picking::pointer::PointerInteraction;
+use bevy::render::view::screenshot::{save_to_disk, Screenshot, ScreenshotCaptured};
+use bevy::utils::SystemTime;
 use bevy::{math::*, prelude::*, window::PrimaryWindow};
+use serde::{Deserialize, Serialize};
 
 use crate::camera::*;
+use crate::debug::*;
 use crate::input::*;
 use building::*;
+use feedback::*;
+use measure::*;
 use player::*;
+use save::*;
+use terrain::*;
+use time_of_day::*;
 use world::*;
 
 pub mod building;
+pub mod feedback;
+pub mod measure;
 pub mod player;
+pub mod save;
+pub mod terrain;
+pub mod time_of_day;
 pub mod world;
 
 /// All game systems and rules
@@ -29,7 +44,12 @@
 impl Plugin for GamePlugin {
     fn build(&self, app: &mut App) {
         app.add_plugins(build_plugin);
+        app.add_plugins(feedback_plugin);
+        app.add_plugins(measure_plugin);
         app.add_plugins(player_plugin);
+        app.add_plugins(save_plugin);
+        app.add_plugins(terrain_plugin);
+        app.add_plugins(time_of_day_plugin);
         app.add_plugins(world_plugin);
 
         app.add_systems(PreUpdate, update_cursor.after(InputManagerSystem::Update));
@@ -37,8 +57,13 @@ fn build(&self, app: &mut App) {
             Update,
             (
                 process_state_change,
-                // draw_mesh_intersections,
-                draw_build_grid.run_if(in_player_state(PlayerState::Building)),
+                take_screenshot,
+                draw_mesh_intersections.run_if(debug_gizmo_enabled(|x| x.mesh_intersections)),
+                draw_build_grid
+                    .run_if(in_player_state(PlayerState::Building))
+                    .run_if(debug_gizmo_enabled(|x| x.build_grid)),
+                draw_rotation_mode_indicator.run_if(in_player_state(PlayerState::Building)),
+                send_snap_feedback,
                 // snap_building_preview_to_build_pos,
                 // validate_building_preview.run_if(on_timer(Duration::from_secs(1))),
                 // process_view_state_input.run_if(in_player_state(PlayerState::Viewing)),
@@ -47,16 +72,48 @@ fn build(&self, app: &mut App) {
             ),
         );
         app.register_type::<PlayerCursor>();
+        app.init_resource::<BuildGridSettings>();
+        app.register_type::<BuildGridSettings>();
+        app.init_resource::<BuildRotationSettings>();
+        app.register_type::<BuildRotationSettings>();
+    }
+}
+
+#[derive(Resource, Reflect)]
+#[reflect(Resource)]
+pub struct BuildGridSettings {
+    // Size of a grid cell we snap to
+    pub cell_size: f32,
+}
+
+impl Default for BuildGridSettings {
+    fn default() -> Self {
+        BuildGridSettings { cell_size: 1.0 }
+    }
+}
+
+#[derive(Resource, Reflect)]
+#[reflect(Resource)]
+pub struct BuildRotationSettings {
+    // Angle in radians we rotate by when snapping rotation
+    pub snap_increment: f32,
+}
+
+impl Default for BuildRotationSettings {
+    fn default() -> Self {
+        BuildRotationSettings {
+            snap_increment: 15.0_f32.to_radians(),
+        }
     }
 }
 
 #[derive(Component)]
 pub struct NetOwner;
 
-#[derive(Default, Reflect, PartialEq)]
+#[derive(Default, Reflect, PartialEq, Clone, Debug, DisplayDebug, Serialize, Deserialize)]
 pub enum PathRotationMode {
     #[default]
-    // Keep aligned with start joint
+    // Keep aligned with start joint, offsetting the end sideways gives an S-curve for lane shifts
     Straight,
     // Share same angle between start and end joint
     Curve,
@@ -64,44 +121,117 @@ pub enum PathRotationMode {
     Chase,
 }
 
+impl PathRotationMode {
+    pub fn next(&self) -> Self {
+        match self {
+            PathRotationMode::Straight => PathRotationMode::Curve,
+            PathRotationMode::Curve => PathRotationMode::Chase,
+            PathRotationMode::Chase => PathRotationMode::Straight,
+        }
+    }
+
+    /// Direction the end joint points to, both forwards point away from the rail
+    ///
+    /// `towards` is the direction from the start to the end point
+    pub fn compute_end_forward(
+        &self,
+        start_forward: Vec3,
+        towards: Vec3,
+        manual_rotation: f32,
+    ) -> Dir3 {
+        let end_forward = match self {
+            PathRotationMode::Straight => -start_forward,
+            PathRotationMode::Curve => {
+                // Reflect around the normal of the line between both points so both ends share the same angle
+                let normal = Vec2::from_angle(PI * 0.5).rotate(towards.xz());
+                (-start_forward).reflect(vec3(normal.x, 0., normal.y))
+            }
+            PathRotationMode::Chase => towards,
+        };
+        Dir3::new(Quat::from_rotation_y(manual_rotation) * end_forward).unwrap_or(Dir3::X)
+    }
+}
+
 fn update_cursor(
     windows: Query<&Window, With<PrimaryWindow>>,
     cameras: Query<(&PanOrbitCamera, &Camera, &GlobalTransform)>,
     mut q: Query<(&mut PlayerCursor, Option<&ActionState<PlayerBuildAction>>), With<NetOwner>>,
+    grid: Res<BuildGridSettings>,
+    rotation: Res<BuildRotationSettings>,
+    terrain: Res<Terrain>,
     time: Res<Time>,
 ) {
-    let window = windows.single();
+    // No window when running headless
+    let window = windows.get_single().ok();
     let (pan_cam, camera, global_transform) = cameras.iter().find(|(_, c, _)| c.is_active).unwrap();
     let (mut cursor, input) = q.single_mut();
 
     // Check if cursor is in window
     cursor.prev_world_pos = cursor.world_pos;
-    cursor.screen_pos = window.cursor_position();
+    cursor.screen_pos = window.and_then(|window| window.cursor_position());
     if let Some(ray) = cursor
         .screen_pos
         .and_then(|pos| camera.viewport_to_world(global_transform, pos).ok())
     {
-        // Check if cursor intersects ground
-        if let Some(len) = ray.intersect_plane(Vec3::ZERO, InfinitePlane3d::new(Vec3::Y)) {
-            cursor.world_pos = ray.origin + ray.direction * len;
+        // Check if cursor intersects our build plane
+        if let Some(len) =
+            ray.intersect_plane(Vec3::Y * cursor.build_height, InfinitePlane3d::new(Vec3::Y))
+        {
+            let pos = ray.origin + ray.direction * len;
+            cursor.world_pos = pos.with_y(terrain.height_at(pos.x, pos.z) + cursor.build_height);
             // gizmos.sphere(cursor.position, Quat::IDENTITY, 10.0, RED);
         }
     } else {
         // Set these values to camera center, in case we do gamepad implementation
         cursor.world_pos = pan_cam.center;
     }
-    cursor.world_grid_pos = cursor.world_pos.round();
+    // Cell size and snap increment can be set to 0 in the inspector, which we treat as no snapping
+    let world_grid_pos = if grid.cell_size > 0.0 {
+        (cursor.world_pos.xz() / grid.cell_size).round() * grid.cell_size
+    } else {
+        cursor.world_pos.xz()
+    };
+    // Keep the same height above ground as the cursor
+    let height_above_ground =
+        cursor.world_pos.y - terrain.height_at(cursor.world_pos.x, cursor.world_pos.z);
+    cursor.world_grid_pos = vec3(
+        world_grid_pos.x,
+        terrain.height_at(world_grid_pos.x, world_grid_pos.y) + height_above_ground,
+        world_grid_pos.y,
+    );
 
     if let Some(input) = input {
+        const BUILD_HEIGHT_STEP: f32 = 1.0;
+        cursor.build_height = (cursor.build_height
+            + input.value(&PlayerBuildAction::AdjustBuildHeight) * BUILD_HEIGHT_STEP)
+            .max(0.0);
+
         if input.just_pressed(&PlayerBuildAction::ToggleSnapToGrid) {
             cursor.should_snap_to_grid = !cursor.should_snap_to_grid;
         }
 
-        if input.pressed(&PlayerBuildAction::Rotate) {
-            cursor.manual_rotation -= PI * 0.5 * time.delta_secs();
+        if input.just_pressed(&PlayerBuildAction::ToggleSnapRotation) {
+            cursor.should_snap_rotation = !cursor.should_snap_rotation;
         }
-        if input.pressed(&PlayerBuildAction::CounterRotate) {
-            cursor.manual_rotation += PI * 0.5 * time.delta_secs();
+
+        if cursor.should_snap_rotation && rotation.snap_increment > 0.0 {
+            // Step a single increment per press so we always land on a snapped angle
+            let step = rotation.snap_increment;
+            let snapped = (cursor.manual_rotation / step).round() * step;
+            if input.just_pressed(&PlayerBuildAction::Rotate) {
+                cursor.manual_rotation = snapped - step;
+            } else if input.just_pressed(&PlayerBuildAction::CounterRotate) {
+                cursor.manual_rotation = snapped + step;
+            } else {
+                cursor.manual_rotation = snapped;
+            }
+        } else {
+            if input.pressed(&PlayerBuildAction::Rotate) {
+                cursor.manual_rotation -= PI * 0.5 * time.delta_secs();
+            }
+            if input.pressed(&PlayerBuildAction::CounterRotate) {
+                cursor.manual_rotation += PI * 0.5 * time.delta_secs();
+            }
         }
 
         const SNAP_ROT: f32 = PI * 0.5;
@@ -115,11 +245,7 @@ fn update_cursor(
         }
 
         if input.just_pressed(&PlayerBuildAction::CyclePathRotateMode) {
-            cursor.rotation_mode = match cursor.rotation_mode {
-                PathRotationMode::Straight => PathRotationMode::Curve,
-                PathRotationMode::Curve => PathRotationMode::Chase,
-                PathRotationMode::Chase => PathRotationMode::Straight,
-            };
+            cursor.rotation_mode = cursor.rotation_mode.next();
             cursor.manual_rotation = 0.;
         }
     }
@@ -136,7 +262,6 @@ fn process_state_change(
     mut q: Query<
         (
             Entity,
-            &PlayerCursor,
             &mut PlayerState,
             Option<&ActionState<PlayerViewAction>>,
             Option<&ActionState<PlayerBuildAction>>,
@@ -144,12 +269,11 @@ fn process_state_change(
         With<NetOwner>,
     >,
     mut previews: Query<&mut BuildingPreview, With<NetOwner>>,
-    mut ev_player_state: EventWriter<PlayerStateEvent>,
     mut exit: EventWriter<AppExit>,
-    mut cancel_mouse_pos: Local<Vec2>,
+    mouse_motion: Res<AccumulatedMouseMotion>,
+    mut cancel_mouse_travel: Local<f32>,
 ) {
-    let (e, cursor, mut state, view_input, build_input) = q.single_mut();
-    let old_state = state.clone();
+    let (e, mut state, view_input, build_input) = q.single_mut();
 
     match *state {
         PlayerState::Viewing => {
@@ -174,26 +298,75 @@ fn process_state_change(
                     state.set(PlayerState::Viewing, &mut c, e);
                 }
 
+                // Right mouse is also used to orbit, so any real drag should not cancel
+                const CANCEL_MAX_MOUSE_TRAVEL: f32 = 4.0;
                 if input.just_pressed(&PlayerBuildAction::CancelWithMouse) {
-                    *cancel_mouse_pos = cursor.screen_pos.unwrap_or_default();
+                    *cancel_mouse_travel = 0.0;
+                } else if input.pressed(&PlayerBuildAction::CancelWithMouse) {
+                    *cancel_mouse_travel += mouse_motion.delta.length();
                 } else if input.just_released(&PlayerBuildAction::CancelWithMouse)
-                    && *cancel_mouse_pos == cursor.screen_pos.unwrap_or_default()
+                    && *cancel_mouse_travel + mouse_motion.delta.length() < CANCEL_MAX_MOUSE_TRAVEL
                 {
                     state.set(PlayerState::Viewing, &mut c, e);
                 }
             }
         }
     }
+}
 
-    if *state != old_state {
-        ev_player_state.send(PlayerStateEvent {
-            old_state: old_state,
-            new_state: state.clone(),
-        });
+/// Marks UI that we hid so it doesn't end up in a screenshot
+#[derive(Component)]
+struct HiddenForScreenshot(Visibility);
+
+fn take_screenshot(
+    mut c: Commands,
+    q: Query<&ActionState<PlayerViewAction>, With<NetOwner>>,
+    mut ui: Query<(Entity, &mut Visibility), With<Node>>,
+) {
+    if !q
+        .iter()
+        .any(|input| input.just_pressed(&PlayerViewAction::Screenshot))
+    {
+        return;
     }
+
+    // Hide our UI for the captured frame, it is restored once the screenshot is captured
+    ui.iter_mut()
+        .filter(|(_, visibility)| **visibility != Visibility::Hidden)
+        .for_each(|(e, mut visibility)| {
+            c.entity(e).insert(HiddenForScreenshot(*visibility));
+            *visibility = Visibility::Hidden;
+        });
+
+    let timestamp = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    // Millisecond precision so quick successive screenshots don't overwrite each other
+    let path = format!("screenshot-{timestamp}.png");
+
+    c.spawn(Screenshot::primary_window())
+        .observe(save_to_disk(path.clone()))
+        .observe(restore_screenshot_ui)
+        .observe(
+            move |_: Trigger<ScreenshotCaptured>, mut ev_feedback: EventWriter<FeedbackEvent>| {
+                ev_feedback.send(FeedbackEvent::info(format!("Saved screenshot to {path}")));
+            },
+        );
 }
 
-fn draw_mesh_intersections(pointers: Query<&PointerInteraction>, mut gizmos: Gizmos) {
+fn restore_screenshot_ui(
+    _trigger: Trigger<ScreenshotCaptured>,
+    mut c: Commands,
+    mut q: Query<(Entity, &mut Visibility, &HiddenForScreenshot)>,
+) {
+    q.iter_mut().for_each(|(e, mut visibility, hidden)| {
+        *visibility = hidden.0;
+        c.entity(e).remove::<HiddenForScreenshot>();
+    });
+}
+
+fn draw_mesh_intersections(pointers: Query<&PointerInteraction>, mut gizmos: Gizmos<DebugGizmos>) {
     for (point, normal) in pointers
         .iter()
         .filter_map(|interaction| interaction.get_nearest_hit())
@@ -207,7 +380,7 @@ fn draw_mesh_intersections(pointers: Query<&PointerInteraction>, mut gizmos: Giz
 fn create_building_preview(
     q: Query<Entity, (With<NetOwner>, With<BuildingPreview>)>,
     mut c: Commands,
-    mut event: EventReader<PlayerStateEvent>,
+    mut event: EventReader<PlayerStateChangedEvent>,
 ) {
     for e in event.read() {
         if e.new_state == PlayerState::Building && e.old_state == PlayerState::Viewing {
@@ -240,16 +413,139 @@ fn validate_building_preview(mut q: Query<&mut BuildingPreview, With<NetOwner>>)
     });
 }
 
-fn draw_build_grid(mut gizmos: Gizmos, q: Query<&PlayerCursor, With<NetOwner>>) {
+fn draw_build_grid(
+    mut gizmos: Gizmos,
+    q: Query<&PlayerCursor, With<NetOwner>>,
+    grid: Res<BuildGridSettings>,
+) {
     let cursor = q.single();
+    // Keep the grid faint unless we actually snap to it
+    let color = if cursor.should_snap_to_grid {
+        Color::srgba(0.9, 0.9, 0.9, 0.6)
+    } else {
+        Color::srgba(0.8, 0.8, 0.8, 0.15)
+    };
 
     gizmos.grid(
         Isometry3d {
             rotation: Quat::from_axis_angle(Vec3::X, -PI * 0.5),
-            translation: vec3(cursor.world_grid_pos.x, 0.01, cursor.world_grid_pos.z).into(),
+            // Grid pos already includes the terrain height
+            translation: (cursor.world_grid_pos + Vec3::Y * 0.01).into(),
         },
         UVec2::splat(16),
-        Vec2::splat(1.0),
-        Color::srgba(0.8, 0.8, 0.8, 0.3),
+        Vec2::splat(grid.cell_size),
+        color,
     );
 }
+
+/// Let the player know when a snap mode is toggled, as it is otherwise hard to notice
+fn send_snap_feedback(
+    q: Query<&PlayerCursor, (With<NetOwner>, Changed<PlayerCursor>)>,
+    mut ev_feedback: EventWriter<FeedbackEvent>,
+    mut last: Local<Option<(bool, bool)>>,
+) {
+    let Ok(cursor) = q.get_single() else {
+        return;
+    };
+    let on_off = |x: bool| if x { "ON" } else { "OFF" };
+
+    let current = (cursor.should_snap_to_grid, cursor.should_snap_rotation);
+    if let Some((grid, rotation)) = *last {
+        if grid != current.0 {
+            ev_feedback.send(FeedbackEvent::info(format!(
+                "Grid snap: {}",
+                on_off(current.0)
+            )));
+        }
+        if rotation != current.1 {
+            ev_feedback.send(FeedbackEvent::info(format!(
+                "Rotation snap: {}",
+                on_off(current.1)
+            )));
+        }
+    }
+    *last = Some(current);
+}
+
+/// Small icon next to the cursor so we can see the rotation mode without reading the input list
+fn draw_rotation_mode_indicator(mut gizmos: Gizmos, q: Query<&PlayerCursor, With<NetOwner>>) {
+    const SIZE: f32 = 1.5;
+    let cursor = q.single();
+    let origin = cursor.build_pos + vec3(SIZE * 2.0, 0.05, SIZE * 2.0);
+    let to_world = |p: Vec2| origin + vec3(p.x, 0.0, p.y) * SIZE;
+    // Quarter circle from the left going up
+    let arc = |steps: usize| {
+        (0..=steps).map(move |i| {
+            let angle = PI * 0.5 * i as f32 / steps as f32;
+            vec2(-angle.cos(), -angle.sin()) + vec2(0.0, 1.0)
+        })
+    };
+
+    match cursor.rotation_mode {
+        PathRotationMode::Straight => {
+            gizmos.arrow(to_world(vec2(-1., 0.)), to_world(vec2(1., 0.)), GREEN_400);
+        }
+        PathRotationMode::Curve => {
+            gizmos.linestrip(arc(8).map(to_world), SKY_400);
+        }
+        PathRotationMode::Chase => {
+            gizmos.linestrip(arc(8).map(to_world), AMBER_400);
+            gizmos.arrow(to_world(vec2(-1., 1.)), to_world(vec2(1., -1.)), AMBER_400);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_dir_eq(a: Dir3, b: Vec3) {
+        assert!(a.distance(b) < 1e-4, "{a:?} != {b:?}");
+    }
+
+    #[test]
+    fn test_compute_end_forward() {
+        let start_forward = Vec3::NEG_X;
+        let towards = vec3(1., 0., 1.).normalize();
+
+        assert_dir_eq(
+            PathRotationMode::Straight.compute_end_forward(start_forward, towards, 0.),
+            Vec3::X,
+        );
+        assert_dir_eq(
+            PathRotationMode::Curve.compute_end_forward(start_forward, towards, 0.),
+            Vec3::Z,
+        );
+        assert_dir_eq(
+            PathRotationMode::Chase.compute_end_forward(start_forward, towards, 0.),
+            towards,
+        );
+    }
+
+    #[test]
+    fn test_compute_end_forward_from_joint() {
+        // Extending east from a joint towards a point shifted north, like a lane shift
+        let start_forward = Vec3::NEG_X;
+        let towards = vec3(4., 0., 1.).normalize();
+
+        let straight = PathRotationMode::Straight.compute_end_forward(start_forward, towards, 0.);
+        let curve = PathRotationMode::Curve.compute_end_forward(start_forward, towards, 0.);
+        let chase = PathRotationMode::Chase.compute_end_forward(start_forward, towards, 0.);
+
+        // Straight ends parallel to the joint, so a sideways offset makes an S
+        assert_dir_eq(straight, Vec3::X);
+        // Curve bends past the line to the end point by as much as the start does
+        assert!(curve.z > towards.z);
+        // Chase ends along the line to the end point
+        assert_dir_eq(chase, towards);
+        assert!(straight.distance(*curve) > 0.1 && straight.distance(*chase) > 0.1);
+    }
+
+    #[test]
+    fn test_compute_end_forward_manual_rotation() {
+        assert_dir_eq(
+            PathRotationMode::Straight.compute_end_forward(Vec3::NEG_X, Vec3::X, PI * 0.5),
+            Vec3::NEG_Z,
+        );
+    }
+}
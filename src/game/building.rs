@@ -2,12 +2,14 @@
 use super::*;
 use bevy::pbr::NotShadowCaster;
 
+use history::*;
 use rail::*;
+pub mod history;
 pub mod rail;
 
 pub(super) fn build_plugin(app: &mut App) {
     app.add_systems(Startup, load_assets);
-    app.add_plugins(rail_plugin);
+    app.add_plugins((rail_plugin, history_plugin));
     // app.add_systems(
     //     Update,
     //     (
@@ -0,0 +1,115 @@
+//! Undo and redo of build actions
+use super::*;
+use rail::rail_planner::RailPlanner;
+use rail::RailLimit;
+
+pub(super) fn history_plugin(app: &mut App) {
+    app.init_resource::<BuildHistory>();
+    app.add_systems(
+        Update,
+        handle_build_history_input.run_if(any_with_component::<InputContext<PlayerBuildAction>>),
+    );
+}
+
+/// An action that can be undone and redone
+pub enum BuildCommand {
+    PlaceRail {
+        rail_entity: Entity,
+        // Plan the rail was built with, used to rebuild and reconnect it
+        plan: RailPlanner,
+    },
+}
+
+impl BuildCommand {
+    fn remap(&mut self, old: Entity, new: Entity) {
+        match self {
+            BuildCommand::PlaceRail { rail_entity, plan } => {
+                if *rail_entity == old {
+                    *rail_entity = new;
+                }
+                plan.start_joint
+                    .iter_mut()
+                    .chain(plan.end_joint.iter_mut())
+                    .filter(|joint| joint.rail_entity == old)
+                    .for_each(|joint| joint.rail_entity = new);
+            }
+        }
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct BuildHistory {
+    undo: Vec<BuildCommand>,
+    redo: Vec<BuildCommand>,
+}
+
+impl BuildHistory {
+    /// Record a new action, this invalidates anything we could redo
+    pub fn push(&mut self, command: BuildCommand) {
+        self.undo.push(command);
+        self.redo.clear();
+    }
+
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+    }
+
+    // Redoing respawns entities, so we need to update any commands that refer to the old ones
+    fn remap(&mut self, old: Entity, new: Entity) {
+        self.undo
+            .iter_mut()
+            .chain(self.redo.iter_mut())
+            .for_each(|command| command.remap(old, new));
+    }
+}
+
+fn handle_build_history_input(
+    mut c: Commands,
+    mut history: ResMut<BuildHistory>,
+    mut rails: Query<&mut Rail>,
+    limit: Res<RailLimit>,
+    planners: Query<Entity, (With<RailPlanner>, With<NetOwner>)>,
+    input: Query<&ActionState<PlayerBuildAction>, With<NetOwner>>,
+    mut ev_feedback: EventWriter<FeedbackEvent>,
+) {
+    let input = input.single();
+
+    let mut changed = false;
+    if input.just_pressed(&PlayerBuildAction::Undo) {
+        if let Some(command) = history.undo.pop() {
+            match &command {
+                BuildCommand::PlaceRail { rail_entity, .. } => {
+                    rails
+                        .iter_mut()
+                        .for_each(|mut rail| rail.disconnect(*rail_entity));
+                    c.entity(*rail_entity).despawn();
+                }
+            }
+            history.redo.push(command);
+            changed = true;
+        }
+    } else if input.just_pressed(&PlayerBuildAction::Redo) {
+        if !history.redo.is_empty() && limit.is_reached(rails.iter().len()) {
+            ev_feedback.send(FeedbackEvent::error("Rail limit reached"));
+        } else if let Some(mut command) = history.redo.pop() {
+            match &mut command {
+                BuildCommand::PlaceRail { rail_entity, plan } => {
+                    let mut rail = c.spawn_empty();
+                    let new_entity = rail.id();
+                    rail.insert(Rail::new(new_entity, &mut rails, plan));
+                    let old_entity = *rail_entity;
+                    *rail_entity = new_entity;
+                    history.remap(old_entity, new_entity);
+                }
+            }
+            history.undo.push(command);
+            changed = true;
+        }
+    }
+
+    // An active planner might be extending from a rail we just removed
+    if changed {
+        planners.iter().for_each(|e| c.entity(e).despawn());
+    }
+}
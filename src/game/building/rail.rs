@@ -1,5 +1,6 @@
 use super::*;
-use bevy::math::bounding::{BoundingSphere, BoundingVolume, IntersectsVolume};
+use bevy::math::bounding::{Aabb3d, BoundingSphere, BoundingVolume, IntersectsVolume};
+use bevy::utils::HashSet;
 
 use rail_planner::*;
 
@@ -12,7 +13,19 @@ pub(super) fn rail_plugin(app: &mut App) {
         rail_graph::rail_graph_plugin,
         rail_planner::rail_planner_plugin,
     ));
-    app.add_systems(Update, debug_draw_rail_path);
+    app.add_systems(
+        Update,
+        (
+            draw_rail_path,
+            debug_draw_rail_path.run_if(debug_gizmo_enabled(|x| x.rail_path)),
+            debug_draw_connected_rails.run_if(debug_gizmo_enabled(|x| x.connected_rails)),
+            cycle_rail_class.run_if(any_with_component::<InputContext<PlayerBuildAction>>),
+        ),
+    );
+    app.init_resource::<RailClasses>();
+    app.register_type::<RailClasses>();
+    app.init_resource::<RailLimit>();
+    app.register_type::<RailLimit>();
 }
 
 #[derive(Resource)]
@@ -40,7 +53,115 @@ pub fn create_rail_asset(
 const RAIL_MIN_LENGTH: f32 = 10.;
 const RAIL_MIN_RADIANS: f32 = 10.0 * PI / 180.0;
 const RAIL_MAX_RADIANS: f32 = 22.5 * PI / 180.0;
-const RAIL_CURVES_MAX: usize = (RAIL_MAX_RADIANS / RAIL_MIN_RADIANS) as usize + 1;
+pub const RAIL_CURVES_MAX: usize = (RAIL_MAX_RADIANS / RAIL_MIN_RADIANS) as usize + 1;
+// Amount of segments we approximate a rail with when measuring it
+const RAIL_SAMPLE_STEPS: usize = 32;
+// Points of a new rail closer than this to an existing rail are considered on top of it
+const RAIL_OVERLAP_DISTANCE: f32 = 1.0;
+// Fraction of a new rail that can lie on top of existing rails before we reject it
+const RAIL_OVERLAP_FRACTION: f32 = 0.5;
+
+/// Geometry rules a rail has to follow to be built
+#[derive(Reflect, Clone)]
+pub struct RailClass {
+    pub name: String,
+    pub min_length: f32,
+    // Minimum delta angle between curves that share a joint
+    pub min_radians: f32,
+    pub max_radians: f32,
+}
+
+impl Default for RailClass {
+    fn default() -> Self {
+        RailClass {
+            name: "Standard".into(),
+            min_length: RAIL_MIN_LENGTH,
+            min_radians: RAIL_MIN_RADIANS,
+            max_radians: RAIL_MAX_RADIANS,
+        }
+    }
+}
+
+/// Budget for how many rails can exist, used for perf testing and scenarios
+#[derive(Resource, Reflect, Default)]
+#[reflect(Resource)]
+pub struct RailLimit {
+    // None means unlimited
+    pub max_rails: Option<usize>,
+}
+
+impl RailLimit {
+    pub fn is_reached(&self, rail_count: usize) -> bool {
+        self.max_rails.is_some_and(|max| rail_count >= max)
+    }
+
+    /// Whether a network of rail_count rails fits in the budget
+    pub fn allows(&self, rail_count: usize) -> bool {
+        self.max_rails.is_none_or(|max| rail_count <= max)
+    }
+}
+
+/// Rail classes we can pick from while building
+#[derive(Resource, Reflect)]
+#[reflect(Resource)]
+pub struct RailClasses {
+    pub classes: Vec<RailClass>,
+    pub active: usize,
+}
+
+impl Default for RailClasses {
+    fn default() -> Self {
+        RailClasses {
+            classes: vec![
+                RailClass::default(),
+                RailClass {
+                    name: "High Speed".into(),
+                    min_length: 20.,
+                    max_radians: 10.0 * PI / 180.0,
+                    ..default()
+                },
+                RailClass {
+                    name: "Yard".into(),
+                    min_length: 5.,
+                    max_radians: 30.0 * PI / 180.0,
+                    ..default()
+                },
+            ],
+            active: 0,
+        }
+    }
+}
+
+impl RailClasses {
+    /// None if there are no classes, the default class should be used then
+    pub fn active(&self) -> Option<&RailClass> {
+        self.classes.get(self.active)
+    }
+}
+
+fn cycle_rail_class(
+    mut classes: ResMut<RailClasses>,
+    input: Query<&ActionState<PlayerBuildAction>, With<NetOwner>>,
+    mut ev_feedback: EventWriter<FeedbackEvent>,
+) {
+    if !input
+        .single()
+        .just_pressed(&PlayerBuildAction::CycleRailClass)
+    {
+        return;
+    }
+
+    // Classes can be removed in the inspector
+    if classes.classes.is_empty() {
+        ev_feedback.send(FeedbackEvent::error("No rail classes to pick from"));
+        return;
+    }
+    classes.active = (classes.active + 1) % classes.classes.len();
+    ev_feedback.send(FeedbackEvent::info(format!(
+        "Rail class: {}",
+        classes.classes[classes.active].name
+    )));
+}
 
 /// Contains the details to build and connect a rail
 #[derive(Component)]
@@ -49,28 +170,9 @@ pub struct Rail {
 }
 
 impl Rail {
-    fn new(self_entity: Entity, q: &mut Query<&mut Rail>, plan: &RailPlanner) -> Rail {
-        let start = plan.start;
-        let end = plan.end;
-        let dir = (end - start).normalize();
-        let size = ((end - start).length()).min(2.5);
-
-        let mut self_state = Rail {
-            joints: [
-                RailPathJoint {
-                    pos: start,
-                    forward: plan.start_forward,
-                    collision: BoundingSphere::new(start + dir * size, size),
-                    n_joints: [None; 3],
-                },
-                RailPathJoint {
-                    pos: end,
-                    forward: plan.end_forward,
-                    collision: BoundingSphere::new(end - dir * size, size),
-                    n_joints: [None; 3],
-                },
-            ],
-        };
+    pub fn new(self_entity: Entity, q: &mut Query<&mut Rail>, plan: &RailPlanner) -> Rail {
+        let mut self_state =
+            Rail::from_joints(plan.start, plan.start_forward, plan.end, plan.end_forward);
 
         let mut connect_joints = |other_joint_ref: RailPathJointRef| {
             let mut other_state = q.get_mut(other_joint_ref.rail_entity).unwrap();
@@ -107,6 +209,90 @@ fn new(self_entity: Entity, q: &mut Query<&mut Rail>, plan: &RailPlanner) -> Rai
 
         self_state
     }
+
+    /// Create a rail without any connections
+    pub fn from_joints(start: Vec3, start_forward: Vec3, end: Vec3, end_forward: Vec3) -> Rail {
+        let dir = (end - start).normalize();
+        let size = ((end - start).length()).min(2.5);
+
+        Rail {
+            joints: [
+                RailPathJoint {
+                    pos: start,
+                    forward: start_forward,
+                    collision: BoundingSphere::new(start + dir * size, size),
+                    n_joints: [None; RAIL_CURVES_MAX],
+                },
+                RailPathJoint {
+                    pos: end,
+                    forward: end_forward,
+                    collision: BoundingSphere::new(end - dir * size, size),
+                    n_joints: [None; RAIL_CURVES_MAX],
+                },
+            ],
+        }
+    }
+
+    /// Points along the curve between both joints, includes both ends
+    pub fn sample_points(&self, steps: usize) -> Vec<Vec3> {
+        sample_curve(
+            create_curve_control_points(
+                self.joints[RAIL_START_JOINT].pos,
+                self.joints[RAIL_START_JOINT].forward,
+                self.joints[RAIL_END_JOINT].pos,
+                self.joints[RAIL_END_JOINT].forward,
+            ),
+            steps,
+        )
+    }
+
+    /// Approximated length of the curve between both joints
+    pub fn length(&self) -> f32 {
+        self.sample_points(RAIL_SAMPLE_STEPS)
+            .windows(2)
+            .map(|x| x[0].distance(x[1]))
+            .sum()
+    }
+
+    /// Approximated closest point on the curve to pos and the direction of the curve at that point
+    pub fn closest_point(&self, pos: Vec3) -> (Vec3, Vec3) {
+        let (point, forward, _) = self.project(pos);
+        (point, forward)
+    }
+
+    /// Approximated curve parameter t in range [0, 1] of the closest point on the curve to pos
+    pub fn closest_t(&self, pos: Vec3) -> f32 {
+        self.project(pos).2
+    }
+
+    fn project(&self, pos: Vec3) -> (Vec3, Vec3, f32) {
+        project_on_polyline(&self.sample_points(RAIL_SAMPLE_STEPS), pos)
+    }
+
+    /// Bounds of the curve, a bezier curve always lies within the hull of its control points
+    pub fn aabb(&self) -> Aabb3d {
+        let points = create_curve_control_points(
+            self.joints[RAIL_START_JOINT].pos,
+            self.joints[RAIL_START_JOINT].forward,
+            self.joints[RAIL_END_JOINT].pos,
+            self.joints[RAIL_END_JOINT].forward,
+        );
+        Aabb3d::from_point_cloud(Isometry3d::IDENTITY, points[0].into_iter())
+    }
+
+    /// Approximated distance from pos to the closest point on the curve
+    pub fn distance_to(&self, pos: Vec3) -> f32 {
+        self.closest_point(pos).0.distance(pos)
+    }
+
+    /// Remove any connections to joints of the given rail
+    pub fn disconnect(&mut self, rail_entity: Entity) {
+        self.joints
+            .iter_mut()
+            .flat_map(|joint| joint.n_joints.iter_mut())
+            .filter(|n_joint| n_joint.is_some_and(|x| x.rail_entity == rail_entity))
+            .for_each(|n_joint| *n_joint = None);
+    }
 }
 
 pub fn create_curve_control_points(
@@ -125,6 +311,61 @@ pub fn create_curve_control_points(
     ]]
 }
 
+/// Evenly sample the curve from points generated by create_curve_control_points
+pub fn sample_curve(points: [[Vec3; 4]; 1], steps: usize) -> Vec<Vec3> {
+    CubicBezier::new(points)
+        .to_curve()
+        .unwrap()
+        .iter_positions(steps)
+        .collect()
+}
+
+/// Closest point on the polyline to pos, the direction of the line there and how far along the line it is in [0, 1]
+pub fn project_on_polyline(points: &[Vec3], pos: Vec3) -> (Vec3, Vec3, f32) {
+    let segments = points.len().saturating_sub(1).max(1) as f32;
+    points
+        .windows(2)
+        .enumerate()
+        .map(|(i, x)| {
+            let segment = x[1] - x[0];
+            let t = ((pos - x[0]).dot(segment) / segment.length_squared().max(f32::EPSILON))
+                .clamp(0.0, 1.0);
+            (
+                x[0] + segment * t,
+                segment.normalize_or_zero(),
+                (i as f32 + t) / segments,
+            )
+        })
+        .min_by(|a, b| {
+            a.0.distance_squared(pos)
+                .total_cmp(&b.0.distance_squared(pos))
+        })
+        .unwrap()
+}
+
+/// Find the rail closest to pos and its distance
+pub fn get_closest_rail<'a>(
+    rails: impl Iterator<Item = (Entity, &'a Rail)>,
+    pos: Vec3,
+) -> Option<(Entity, f32)> {
+    rails
+        .map(|(e, rail)| (e, rail.distance_to(pos)))
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+}
+
+/// Find the rail pos lies on within tolerance, and the curve parameter t of pos on that rail
+pub fn is_on_rail<'a>(
+    pos: Vec3,
+    tolerance: f32,
+    rails: impl Iterator<Item = (Entity, &'a Rail)>,
+) -> Option<(Entity, f32)> {
+    rails
+        .map(|(e, rail)| (e, rail, rail.distance_to(pos)))
+        .filter(|(_, _, distance)| *distance <= tolerance)
+        .min_by(|a, b| a.2.total_cmp(&b.2))
+        .map(|(e, rail, _)| (e, rail.closest_t(pos)))
+}
+
 /// Use points generated by create_curve_points
 pub fn create_curve_points(points: [[Vec3; 4]; 1]) -> Vec<Vec3> {
     let start = points[0][0];
@@ -181,6 +422,28 @@ pub struct RailPathJointRef {
     pub joint_idx: usize,
 }
 
+/// Collect every rail that can be reached from the given rail, including itself
+pub fn get_connected_rails(rail_entity: Entity, q: &Query<&Rail>) -> HashSet<Entity> {
+    let mut visited = HashSet::new();
+    let mut stack = vec![rail_entity];
+    while let Some(e) = stack.pop() {
+        let Ok(rail) = q.get(e) else {
+            continue;
+        };
+        if !visited.insert(e) {
+            continue;
+        }
+        stack.extend(
+            rail.joints
+                .iter()
+                .flat_map(|joint| joint.n_joints.iter().flatten())
+                .map(|n_joint| n_joint.rail_entity)
+                .filter(|n_rail| !visited.contains(n_rail)),
+        );
+    }
+    visited
+}
+
 fn get_joint_collision(rail_path: &Rail, sphere: BoundingSphere) -> Option<&RailPathJoint> {
     if rail_path.joints[RAIL_START_JOINT]
         .collision
@@ -197,18 +460,9 @@ fn get_joint_collision(rail_path: &Rail, sphere: BoundingSphere) -> Option<&Rail
     }
 }
 
-pub fn debug_draw_rail_path(
-    mut gizmos: Gizmos,
-    q: Query<&Rail>,
-    preview: Query<&RailPlanner>,
-    cursor: Query<&PlayerCursor, With<NetOwner>>,
-) {
-    let cursor = cursor.single();
-    let cursor_sphere = BoundingSphere::new(cursor.build_pos, 0.1);
-    let preview_exists = !preview.is_empty();
-
+/// Rails have no meshes yet, so this is how players see them
+fn draw_rail_path(mut gizmos: Gizmos, q: Query<&Rail>) {
     q.into_iter().for_each(|state| {
-        // Draw line
         let points = create_curve_control_points(
             state.joints[RAIL_START_JOINT].pos,
             state.joints[RAIL_START_JOINT].forward,
@@ -219,7 +473,20 @@ pub fn debug_draw_rail_path(
         let curve = CubicBezier::new(points).to_curve().unwrap();
         const STEPS: usize = 10;
         gizmos.linestrip(curve.iter_positions(STEPS), Color::WHITE);
+    });
+}
+
+pub fn debug_draw_rail_path(
+    mut gizmos: Gizmos<DebugGizmos>,
+    q: Query<&Rail>,
+    preview: Query<&RailPlanner>,
+    cursor: Query<&PlayerCursor, With<NetOwner>>,
+) {
+    let cursor = cursor.single();
+    let cursor_sphere = BoundingSphere::new(cursor.build_pos, 0.1);
+    let preview_exists = !preview.is_empty();
 
+    q.into_iter().for_each(|state| {
         // Draw forwards
         gizmos.line(
             state.joints[RAIL_START_JOINT].pos,
@@ -282,3 +549,50 @@ pub fn debug_draw_rail_path(
         draw_joint(&state.joints[RAIL_END_JOINT]);
     });
 }
+
+fn debug_draw_connected_rails(
+    mut gizmos: Gizmos<DebugGizmos>,
+    q: Query<&Rail>,
+    rails: Query<(Entity, &Rail)>,
+    preview: Query<(), With<RailPlanner>>,
+    cursor: Query<&PlayerCursor, With<NetOwner>>,
+) {
+    if !preview.is_empty() {
+        return;
+    }
+
+    let cursor_sphere = BoundingSphere::new(cursor.single().build_pos, 0.1);
+    let Some((hovered, _)) = rails
+        .iter()
+        .find(|(_, rail)| get_joint_collision(rail, cursor_sphere).is_some())
+    else {
+        return;
+    };
+
+    get_connected_rails(hovered, &q).into_iter().for_each(|e| {
+        let rail = q.get(e).unwrap();
+        gizmos.linestrip(
+            rail.sample_points(RAIL_SAMPLE_STEPS)
+                .into_iter()
+                .map(|pos| pos + Vec3::Y * 0.1),
+            Color::srgb(1.0, 0.8, 0.1),
+        );
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_on_rail() {
+        let e = Entity::from_raw(1);
+        let rail = Rail::from_joints(Vec3::ZERO, Vec3::NEG_X, Vec3::X * 10.0, Vec3::X);
+
+        let (on, t) = is_on_rail(vec3(2.5, 0., 0.1), 0.5, [(e, &rail)].into_iter()).unwrap();
+        assert_eq!(on, e);
+        assert!((t - 0.25).abs() < 0.05, "t was {t}");
+
+        assert!(is_on_rail(vec3(5.0, 0., 2.0), 0.5, [(e, &rail)].into_iter()).is_none());
+    }
+}
@@ -6,13 +6,17 @@
 ///
 /// We store this as seperate graphs, as construction can be done on worked
 /// threads and we want to optimize the graphs for algorithm
+use crate::debug::*;
 use bevy::prelude::*;
 use petgraph::prelude::*;
 
 pub fn rail_graph_plugin(app: &mut App) {
     {
         app.add_systems(Startup, test_rail_graph);
-        app.add_systems(Update, debug_rail_graph);
+        app.add_systems(
+            Update,
+            debug_rail_graph.run_if(debug_gizmo_enabled(|x| x.rail_graph)),
+        );
     }
 }
 
@@ -48,7 +52,7 @@ fn test_rail_graph(mut c: Commands) {
     c.spawn(RailGraph(graph));
 }
 
-fn debug_rail_graph(mut gizmos: Gizmos, q: Query<&RailGraph>) {
+fn debug_rail_graph(mut gizmos: Gizmos<DebugGizmos>, q: Query<&RailGraph>) {
     q.into_iter().for_each(|graph| {
         graph.0.edge_indices().for_each(|edge| {
             if let Some((start, end)) = graph.0.edge_endpoints(edge) {
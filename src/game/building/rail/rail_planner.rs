@@ -1,6 +1,6 @@
-use std::f32::consts::FRAC_PI_2;
-
-use bevy::math::vec3;
+use bevy::ecs::system::SystemState;
+use bevy::math::bounding::{Aabb3d, BoundingVolume, IntersectsVolume};
+use bevy::math::Vec3A;
 
 /// Logic responsible for generating a preview of what RailBuilding will be built
 use super::*;
@@ -10,11 +10,13 @@ pub fn rail_planner_plugin(app: &mut App) {
         Update,
         (
             (
-                create_rail_planner,
+                toggle_snap_to_nearest_heading,
+                create_rail_planner.run_if(not(any_with_component::<Measurement>)),
                 update_rail_planner,
                 draw_rail_planner,
-                preview_initial_rail_planner_placement
-                    .run_if(not(any_with_component::<RailPlanner>)),
+                preview_initial_rail_planner_placement.run_if(not(
+                    any_with_component::<RailPlanner>.or(any_with_component::<Measurement>),
+                )),
             )
                 .run_if(any_with_component::<InputContext<PlayerBuildAction>>),
             destroy_rail_planner,
@@ -22,7 +24,7 @@ pub fn rail_planner_plugin(app: &mut App) {
     );
 }
 
-#[derive(Component)]
+#[derive(Component, Clone)]
 #[require(Text, Node)]
 pub struct RailPlanner {
     pub start: Vec3,
@@ -48,16 +50,307 @@ fn new(start_pos: Vec3) -> Self {
             status: RailPlannerStatus::Valid,
         }
     }
+
+    /// Check if we can build this plan next to the existing rails
+    pub fn validate(&self, rails: &[(Entity, &Rail)], class: &RailClass) -> RailPlannerStatus {
+        let length = (self.end - self.start).length();
+        if length < class.min_length && self.end_joint.is_none() {
+            return RailPlannerStatus::RailTooShort(length, class.min_length);
+            // TODO: Check joints
+        }
+
+        // Joints only have room for a fixed amount of connections
+        if [self.start_joint, self.end_joint]
+            .into_iter()
+            .flatten()
+            .filter_map(|joint_ref| {
+                rails
+                    .iter()
+                    .find(|(e, _)| *e == joint_ref.rail_entity)
+                    .map(|(_, rail)| &rail.joints[joint_ref.joint_idx])
+            })
+            .any(|joint| joint.get_empty_curve_idx().is_none())
+        {
+            return RailPlannerStatus::JointFull;
+        }
+
+        let control_points =
+            create_curve_control_points(self.start, self.start_forward, self.end, self.end_forward);
+        let points: Vec<Vec3> = create_curve_points(control_points);
+        let first_segment = points[1] - points[0];
+        let angle = points
+            .iter()
+            .zip(points.iter().skip(1).zip(points.iter().skip(2)))
+            .fold(
+                (-self.start_forward).angle_between(first_segment),
+                |max, (left, (middle, right))| {
+                    let left = middle - left;
+                    let right = right - middle;
+                    left.angle_between(right).max(max)
+                },
+            );
+        if angle > class.max_radians {
+            return RailPlannerStatus::CurveTooSharp(angle);
+        }
+
+        // Rails branching from the same joint need to head in distinct enough directions
+        let shallowest = [
+            (self.start_joint, self.start, self.end),
+            (self.end_joint, self.end, self.start),
+        ]
+        .into_iter()
+        .filter_map(|(joint_ref, from, to)| joint_ref.map(|x| (x, from, to)))
+        .filter_map(|(joint_ref, from, to)| {
+            rails
+                .iter()
+                .find(|(e, _)| *e == joint_ref.rail_entity)
+                .map(|(_, rail)| (&rail.joints[joint_ref.joint_idx], from, to))
+        })
+        .flat_map(|(joint, from, to)| {
+            joint.n_joints.iter().flatten().filter_map(move |n_joint| {
+                rails
+                    .iter()
+                    .find(|(e, _)| *e == n_joint.rail_entity)
+                    .map(|(_, rail)| {
+                        let other_end = rail.joints[1 - n_joint.joint_idx].pos;
+                        (other_end - from).angle_between(to - from)
+                    })
+            })
+        })
+        .fold(f32::INFINITY, f32::min);
+        if shallowest < class.min_radians {
+            return RailPlannerStatus::CurveTooShallow(shallowest);
+        }
+
+        // This runs every frame while planning, so only sample rails that are close enough to overlap, and
+        // sample them once
+        let samples = sample_curve(control_points, RAIL_SAMPLE_STEPS);
+        let bounds = Aabb3d::from_point_cloud(Isometry3d::IDENTITY, samples.iter().copied())
+            .grow(Vec3A::splat(RAIL_OVERLAP_DISTANCE));
+        let nearby: Vec<Vec<Vec3>> = rails
+            .iter()
+            .filter(|(_, rail)| rail.aabb().intersects(&bounds))
+            .map(|(_, rail)| rail.sample_points(RAIL_SAMPLE_STEPS))
+            .collect();
+        let overlapping = samples
+            .iter()
+            .filter(|pos| {
+                nearby.iter().any(|points| {
+                    project_on_polyline(points, **pos).0.distance(**pos) < RAIL_OVERLAP_DISTANCE
+                })
+            })
+            .count();
+        let overlap = overlapping as f32 / samples.len() as f32;
+        if overlap > RAIL_OVERLAP_FRACTION {
+            return RailPlannerStatus::OverlapsExisting(overlap);
+        }
+
+        RailPlannerStatus::Valid
+    }
 }
 
-#[derive(Default, PartialEq)]
+#[derive(Default, PartialEq, Clone, Debug)]
 pub enum RailPlannerStatus {
     #[default]
     Valid,
     CurveTooSharp(f32),
     // Our delta angle is too close to any other curves in our joint
     CurveTooShallow(f32),
-    RailTooShort(f32),
+    // Start or end joint has no room for another connection
+    JointFull,
+    // Length and the minimum length
+    RailTooShort(f32, f32),
+    // Fraction of the rail that lies on top of existing rails
+    OverlapsExisting(f32),
+}
+
+impl fmt::Display for RailPlannerStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RailPlannerStatus::Valid => Ok(()),
+            RailPlannerStatus::CurveTooSharp(x) => {
+                write!(f, "Curve Too Sharp {:.2}", x.to_degrees())
+            }
+            RailPlannerStatus::JointFull => write!(f, "Joint Full"),
+            RailPlannerStatus::CurveTooShallow(x) => {
+                write!(f, "Curve Too Shallow {:.2}", x.to_degrees())
+            }
+            RailPlannerStatus::RailTooShort(x, min) => {
+                write!(f, "Rail Too Short {:.2} < {:2}", x, min)
+            }
+            RailPlannerStatus::OverlapsExisting(x) => {
+                write!(f, "Overlaps Existing Rail {:.0}%", x * 100.0)
+            }
+        }
+    }
+}
+
+/// Returned when build_rails_through can't build the given points
+#[derive(Debug, PartialEq)]
+pub enum RailBuildError {
+    // Segment i goes from points[i] to points[i + 1]
+    InvalidSegment {
+        segment: usize,
+        status: RailPlannerStatus,
+    },
+    // Building would exceed the RailLimit
+    RailLimitReached(usize),
+}
+
+impl fmt::Display for RailBuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RailBuildError::InvalidSegment { segment, status } => {
+                write!(f, "Segment {segment}: {status}")
+            }
+            RailBuildError::RailLimitReached(max) => write!(f, "Rail limit of {max} reached"),
+        }
+    }
+}
+
+impl std::error::Error for RailBuildError {}
+
+fn find_joint_at<'a>(
+    rails: &[(Entity, &'a Rail)],
+    pos: Vec3,
+) -> Option<(RailPathJointRef, &'a RailPathJoint)> {
+    let sphere = BoundingSphere::new(pos, 0.1);
+    rails.iter().find_map(|(e, rail)| {
+        get_joint_collision(rail, sphere).map(|joint| {
+            let joint_idx = if rail.joints[RAIL_START_JOINT].pos == joint.pos {
+                RAIL_START_JOINT
+            } else {
+                RAIL_END_JOINT
+            };
+            (
+                RailPathJointRef {
+                    rail_entity: *e,
+                    joint_idx,
+                },
+                joint,
+            )
+        })
+    })
+}
+
+fn rail_count(world: &mut World) -> usize {
+    world.query::<&Rail>().iter(world).count()
+}
+
+/// Build connected rails through the given points without any player input, this is what the planner
+/// would build when curving each segment from the previous one using the active rail class. The ends connect to
+/// existing joints they land on.
+/// Nothing is built if any of the segments is invalid.
+pub fn build_rails_through(
+    world: &mut World,
+    points: &[Vec3],
+) -> Result<Vec<Entity>, RailBuildError> {
+    if points.len() < 2 {
+        return Ok(vec![]);
+    }
+
+    let class = world
+        .get_resource::<RailClasses>()
+        .and_then(|x| x.active().cloned())
+        .unwrap_or_default();
+    let mut plans: Vec<RailPlanner> = vec![];
+    // Rails of the plans so far, so segments of this batch can't overlap each other either
+    let mut planned_rails: Vec<Rail> = vec![];
+    let mut closes_loop = false;
+    {
+        let mut existing = world.query::<(Entity, &Rail)>();
+        let existing: Vec<(Entity, &Rail)> = existing.iter(world).collect();
+
+        for (segment, pair) in points.windows(2).enumerate() {
+            let mut plan = RailPlanner::new(pair[0]);
+            plan.end = pair[1];
+
+            let mut start_forward = plans.last().map(|prev| -prev.end_forward);
+            if start_forward.is_none() {
+                if let Some((joint_ref, joint)) = find_joint_at(&existing, plan.start) {
+                    plan.start = joint.pos;
+                    plan.start_joint = Some(joint_ref);
+                    start_forward = Some(-joint.forward);
+                }
+            }
+            // Only now that the start has snapped to a joint do we know where we head towards
+            let towards = (plan.end - plan.start).normalize_or(Vec3::X);
+            plan.start_forward = start_forward.unwrap_or(-towards);
+            plan.end_forward = PathRotationMode::Curve
+                .compute_end_forward(plan.start_forward, towards, 0.)
+                .into();
+
+            if segment == points.len() - 2 {
+                if let Some((joint_ref, joint)) = find_joint_at(&existing, plan.end) {
+                    plan.end = joint.pos;
+                    plan.end_forward = -joint.forward;
+                    plan.end_joint = Some(joint_ref);
+                } else if let Some(first) = plans.first().filter(|first| {
+                    first.start_joint.is_none() && first.start.distance(plan.end) < 0.1
+                }) {
+                    // We return to our first point, so close the loop with the first rail
+                    closes_loop = true;
+                    plan.end = first.start;
+                    plan.end_forward = -first.start_forward;
+                }
+            }
+
+            let rails: Vec<(Entity, &Rail)> = existing
+                .iter()
+                .copied()
+                .chain(planned_rails.iter().map(|rail| (Entity::PLACEHOLDER, rail)))
+                .collect();
+            plan.status = plan.validate(&rails, &class);
+            if plan.status != RailPlannerStatus::Valid {
+                return Err(RailBuildError::InvalidSegment {
+                    segment,
+                    status: plan.status,
+                });
+            }
+            planned_rails.push(Rail::from_joints(
+                plan.start,
+                plan.start_forward,
+                plan.end,
+                plan.end_forward,
+            ));
+            plans.push(plan);
+        }
+    }
+
+    if let Some(max) = world
+        .get_resource::<RailLimit>()
+        .and_then(|limit| limit.max_rails)
+    {
+        if rail_count(world) + plans.len() > max {
+            return Err(RailBuildError::RailLimitReached(max));
+        }
+    }
+
+    // Build one rail at a time so the next rail can connect to the previous one
+    let mut state = SystemState::<Query<&mut Rail>>::new(world);
+    let mut entities: Vec<Entity> = vec![];
+    let last = plans.len() - 1;
+    for (i, mut plan) in plans.into_iter().enumerate() {
+        if let Some(prev) = entities.last() {
+            plan.start_joint = Some(RailPathJointRef {
+                rail_entity: *prev,
+                joint_idx: RAIL_END_JOINT,
+            });
+        }
+        if closes_loop && i == last {
+            plan.end_joint = Some(RailPathJointRef {
+                rail_entity: entities[0],
+                joint_idx: RAIL_START_JOINT,
+            });
+        }
+
+        let e = world.spawn_empty().id();
+        let rail = Rail::new(e, &mut state.get_mut(world), &plan);
+        world.entity_mut(e).insert(rail);
+        entities.push(e);
+    }
+
+    Ok(entities)
 }
 
 fn create_rail_planner(
@@ -65,7 +358,7 @@ fn create_rail_planner(
     q: Query<Entity, (With<RailPlanner>, With<NetOwner>)>,
     player_state: Query<(&PlayerCursor, &ActionState<PlayerBuildAction>), With<NetOwner>>,
     rail_states: Query<(Entity, &Rail)>,
-    mut event: EventReader<PlayerStateEvent>,
+    mut event: EventReader<PlayerStateChangedEvent>,
 ) {
     // Hacky, but we want to ignore placing this on the switch to view mode
     for e in event.read() {
@@ -101,15 +394,34 @@ fn create_rail_planner(
     }
 }
 
+fn toggle_snap_to_nearest_heading(
+    mut q: Query<(&mut PlayerCursor, &ActionState<PlayerBuildAction>), With<NetOwner>>,
+    mut ev_feedback: EventWriter<FeedbackEvent>,
+) {
+    let (mut cursor, input) = q.single_mut();
+    if input.just_pressed(&PlayerBuildAction::SnapToNearestHeading) {
+        cursor.should_snap_to_nearest_heading = !cursor.should_snap_to_nearest_heading;
+        ev_feedback.send(FeedbackEvent::info(
+            if cursor.should_snap_to_nearest_heading {
+                "Snapping to nearest rail heading"
+            } else {
+                "Stopped snapping to nearest rail heading"
+            },
+        ));
+    }
+}
+
 fn destroy_rail_planner(
     mut c: Commands,
     q: Query<Entity, (With<RailPlanner>, With<NetOwner>)>,
-    mut event: EventReader<PlayerStateEvent>,
+    mut event: EventReader<PlayerStateChangedEvent>,
+    mut ev_feedback: EventWriter<FeedbackEvent>,
 ) {
     for e in event.read() {
         if e.new_state == PlayerState::Viewing && e.old_state == PlayerState::Building {
             q.into_iter().for_each(|e| {
                 c.entity(e).despawn();
+                ev_feedback.send(FeedbackEvent::info("Cancelled rail"));
             });
         }
     }
@@ -127,12 +439,17 @@ fn preview_initial_rail_planner_placement(
     );
 }
 
+#[allow(clippy::too_many_arguments)]
 fn update_rail_planner(
     mut gizmos: Gizmos,
     mut c: Commands,
     mut q: Query<(&mut RailPlanner, &mut Text, &mut Node)>,
     mut rail_states: Query<(Entity, &mut Rail)>,
     player_state: Query<(&PlayerCursor, &ActionState<PlayerBuildAction>), With<NetOwner>>,
+    mut history: ResMut<BuildHistory>,
+    classes: Res<RailClasses>,
+    limit: Res<RailLimit>,
+    mut ev_feedback: EventWriter<FeedbackEvent>,
 ) {
     let (cursor, input) = player_state.single();
     let cursor_sphere = BoundingSphere::new(cursor.build_pos, 0.1);
@@ -146,93 +463,59 @@ fn update_rail_planner(
 
         let delta = plan.end - plan.start;
         let towards = delta.normalize();
-        match cursor.rotation_mode {
-            PathRotationMode::Straight => {
-                if plan.start_joint.is_none() {
-                    plan.start_forward = -towards;
-                    plan.end_forward = towards;
+        // Without a start joint there is no heading to keep, so Straight follows the line to the end
+        if plan.start_joint.is_none() && cursor.rotation_mode == PathRotationMode::Straight {
+            plan.start_forward = -towards;
+        }
+        plan.end_forward = cursor
+            .rotation_mode
+            .compute_end_forward(plan.start_forward, towards, cursor.manual_rotation)
+            .into();
+
+        if cursor.should_snap_to_nearest_heading {
+            // Only snap to rails that are close enough to be meant as a parallel or continuation
+            const SNAP_HEADING_DISTANCE: f32 = 20.0;
+            if let Some((point, forward)) = rail_states
+                .iter()
+                .map(|(_, rail)| rail.closest_point(plan.end))
+                .filter(|(point, _)| point.distance(plan.end) < SNAP_HEADING_DISTANCE)
+                .min_by(|a, b| {
+                    a.0.distance_squared(plan.end)
+                        .total_cmp(&b.0.distance_squared(plan.end))
+                })
+            {
+                plan.end_forward = if forward.dot(plan.end_forward) >= 0.0 {
+                    forward
                 } else {
-                    // plan.end_forward = plan.start_forward.reflect(towards.any_orthonormal_vector());
-                    plan.end_forward = -plan.start_forward;
-                }
-            }
-            PathRotationMode::Curve => {
-                let incidence = -plan.start_forward;
-                let towards_2d = Vec2::from_angle(FRAC_PI_2).rotate(towards.xz());
-                let normal = vec3(towards_2d.x, 0., towards_2d.y);
-                gizmos.line(plan.start, plan.start + normal, Color::BLACK);
-                plan.end_forward = incidence.reflect(normal);
-            }
-            PathRotationMode::Chase => {
-                plan.end_forward = towards;
+                    -forward
+                };
+                gizmos.line(point, plan.end, Color::srgb(1.0, 0.8, 0.1));
             }
         }
-        plan.end_forward = Quat::from_rotation_y(cursor.manual_rotation) * plan.end_forward;
 
         // Check if we hover over a joint for end pos
         plan.end_joint = rail_states.into_iter().find_map(|(e, state)| {
-            get_joint_collision(state, cursor_sphere).and_then(|joint| {
-                plan.end = joint.pos;
-                plan.end_forward = -joint.forward;
-                Some(RailPathJointRef {
-                    rail_entity: e,
-                    joint_idx: if state.joints[RAIL_START_JOINT].pos == joint.pos {
-                        RAIL_START_JOINT
-                    } else {
-                        RAIL_END_JOINT
-                    },
+            get_joint_collision(state, cursor_sphere)
+                // Ending on the joint we start from would give us a rail without length
+                .filter(|joint| joint.pos != plan.start)
+                .and_then(|joint| {
+                    plan.end = joint.pos;
+                    plan.end_forward = -joint.forward;
+                    Some(RailPathJointRef {
+                        rail_entity: e,
+                        joint_idx: if state.joints[RAIL_START_JOINT].pos == joint.pos {
+                            RAIL_START_JOINT
+                        } else {
+                            RAIL_END_JOINT
+                        },
+                    })
                 })
-            })
         });
 
         // Validate our plan
-        let length = delta.length();
-
-        plan.status = if length < RAIL_MIN_LENGTH && plan.end_joint.is_none() {
-            RailPlannerStatus::RailTooShort(delta.length())
-            // TODO: Check joints
-        } else if false {
-            RailPlannerStatus::CurveTooShallow(0.)
-        } else {
-            let points: Vec<Vec3> = create_curve_points(create_curve_control_points(
-                plan.start,
-                plan.start_forward,
-                plan.end,
-                plan.end_forward,
-            ));
-            let first_segment = points[1] - points[0];
-            let angle = points
-                .iter()
-                .zip(points.iter().skip(1).zip(points.iter().skip(2)))
-                .fold(
-                    (-plan.start_forward).angle_between(first_segment),
-                    |max, (left, (middle, right))| {
-                        let left = middle - left;
-                        let right = right - middle;
-                        let angle = left.angle_between(right);
-                        let max = (angle).max(max);
-                        max
-                    },
-                );
-            if angle > RAIL_MAX_RADIANS {
-                RailPlannerStatus::CurveTooSharp(angle)
-            } else {
-                RailPlannerStatus::Valid
-            }
-        };
-
-        text.0 = match plan.status {
-            RailPlannerStatus::Valid => "".into(),
-            RailPlannerStatus::CurveTooSharp(x) => {
-                format!("Curve Too Sharp {:.2}", x.to_degrees()).into()
-            }
-            RailPlannerStatus::CurveTooShallow(x) => {
-                format!("Curve Too Shallow {:.2}", x.to_degrees()).into()
-            }
-            RailPlannerStatus::RailTooShort(x) => {
-                format!("Rail Too Short {:.2} < {:2}", x, RAIL_MIN_LENGTH).into()
-            }
-        };
+        let class = classes.active().cloned().unwrap_or_default();
+        plan.status = plan.validate(&rail_states.iter().collect::<Vec<_>>(), &class);
+        text.0 = plan.status.to_string();
 
         if let Some(pos) = cursor.screen_pos {
             node.left = Val::Px(pos.x);
@@ -241,14 +524,25 @@ fn update_rail_planner(
 
         // We have an intention to build
         if input.just_pressed(&PlayerBuildAction::Interact)
-            && plan.status == RailPlannerStatus::Valid
+            && plan.status != RailPlannerStatus::Valid
+        {
+            ev_feedback.send(FeedbackEvent::error(text.0.clone()));
+        } else if input.just_pressed(&PlayerBuildAction::Interact)
+            && limit.is_reached(rail_states.iter().len())
         {
+            ev_feedback.send(FeedbackEvent::error("Rail limit reached"));
+        } else if input.just_pressed(&PlayerBuildAction::Interact) {
             let mut rail = c.spawn_empty();
             rail.insert(Rail::new(
                 rail.id(),
                 &mut rail_states.transmute_lens::<&mut Rail>().query(),
                 &plan,
             ));
+            history.push(BuildCommand::PlaceRail {
+                rail_entity: rail.id(),
+                plan: plan.clone(),
+            });
+            ev_feedback.send(FeedbackEvent::info("Placed rail"));
             plan.start = plan.end;
             plan.start_forward = -plan.end_forward;
             plan.start_joint = Some(RailPathJointRef {
@@ -293,3 +587,239 @@ fn draw_rail_planner(mut gizmos: Gizmos, q: Query<&RailPlanner>) {
         );
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_rails_through_connects_segments() {
+        let mut world = World::new();
+        let points = [Vec3::ZERO, vec3(20., 0., 0.), vec3(40., 0., 0.)];
+
+        let rails = build_rails_through(&mut world, &points).unwrap();
+        assert_eq!(rails.len(), 2);
+
+        let first = world.get::<Rail>(rails[0]).unwrap();
+        assert!(first.joints[RAIL_END_JOINT]
+            .n_joints
+            .iter()
+            .flatten()
+            .any(|x| x.rail_entity == rails[1] && x.joint_idx == RAIL_START_JOINT));
+
+        // Extending from the end joint connects to the existing rails
+        let more = build_rails_through(&mut world, &[points[2], vec3(60., 0., 0.)]).unwrap();
+        let last = world.get::<Rail>(rails[1]).unwrap();
+        assert!(last.joints[RAIL_END_JOINT]
+            .n_joints
+            .iter()
+            .flatten()
+            .any(|x| x.rail_entity == more[0]));
+    }
+
+    #[test]
+    fn test_build_rails_through_closes_loop() {
+        let mut world = World::new();
+        // Each corner turns around, which is too sharp for standard rails
+        let mut classes = RailClasses::default();
+        classes.active = classes
+            .classes
+            .iter()
+            .position(|x| x.name == "Yard")
+            .unwrap();
+        world.insert_resource(classes);
+
+        // Builds a loop of four rails shaped like a stadium
+        const SIZE: f32 = 100.;
+        let points = [
+            Vec3::ZERO,
+            vec3(SIZE, 0., 0.),
+            vec3(SIZE, 0., SIZE),
+            vec3(0., 0., SIZE),
+            Vec3::ZERO,
+        ];
+
+        let rails = build_rails_through(&mut world, &points).unwrap();
+        assert_eq!(rails.len(), 4);
+
+        let first = world.get::<Rail>(rails[0]).unwrap();
+        assert!(first.joints[RAIL_START_JOINT]
+            .n_joints
+            .iter()
+            .flatten()
+            .any(|x| x.rail_entity == rails[3] && x.joint_idx == RAIL_END_JOINT));
+        let last = world.get::<Rail>(rails[3]).unwrap();
+        assert_eq!(last.joints[RAIL_END_JOINT].pos, Vec3::ZERO);
+        assert!(last.joints[RAIL_END_JOINT]
+            .n_joints
+            .iter()
+            .flatten()
+            .any(|x| x.rail_entity == rails[0] && x.joint_idx == RAIL_START_JOINT));
+    }
+
+    #[test]
+    fn test_validate_overlap() {
+        let a = Entity::from_raw(1);
+        let rail = Rail::from_joints(Vec3::ZERO, Vec3::NEG_X, Vec3::X * 20., Vec3::X);
+        let rails = [(a, &rail)];
+
+        // Laid right on top of the existing rail
+        let mut plan = RailPlanner::new(Vec3::ZERO);
+        plan.start_forward = Vec3::NEG_X;
+        plan.end = Vec3::X * 20.;
+        plan.end_forward = Vec3::X;
+        assert!(matches!(
+            plan.validate(&rails, &RailClass::default()),
+            RailPlannerStatus::OverlapsExisting(_)
+        ));
+
+        // Continuing from the end joint only touches it at the joint
+        let mut plan = RailPlanner::new(Vec3::X * 20.);
+        plan.start_forward = Vec3::NEG_X;
+        plan.start_joint = Some(RailPathJointRef {
+            rail_entity: a,
+            joint_idx: RAIL_END_JOINT,
+        });
+        plan.end = Vec3::X * 40.;
+        plan.end_forward = Vec3::X;
+        assert_eq!(
+            plan.validate(&rails, &RailClass::default()),
+            RailPlannerStatus::Valid
+        );
+    }
+
+    #[test]
+    fn test_validate_rejects_full_joint() {
+        let mut rail = Rail::from_joints(Vec3::ZERO, Vec3::NEG_X, Vec3::X * 20., Vec3::X);
+        rail.joints[RAIL_END_JOINT]
+            .n_joints
+            .iter_mut()
+            .enumerate()
+            .for_each(|(i, n_joint)| {
+                *n_joint = Some(RailPathJointRef {
+                    rail_entity: Entity::from_raw(10 + i as u32),
+                    joint_idx: RAIL_START_JOINT,
+                })
+            });
+        let mut world = World::new();
+        let e = world.spawn(rail).id();
+
+        let err = build_rails_through(&mut world, &[Vec3::X * 20., Vec3::X * 40.]).unwrap_err();
+        assert_eq!(
+            err,
+            RailBuildError::InvalidSegment {
+                segment: 0,
+                status: RailPlannerStatus::JointFull
+            }
+        );
+        assert_eq!(rail_count(&mut world), 1);
+
+        let rail = world.get::<Rail>(e).unwrap();
+        let mut plan = RailPlanner::new(Vec3::X * 20.);
+        plan.start_joint = Some(RailPathJointRef {
+            rail_entity: e,
+            joint_idx: RAIL_END_JOINT,
+        });
+        plan.end = Vec3::X * 40.;
+        assert_eq!(
+            plan.validate(&[(e, rail)], &RailClass::default()),
+            RailPlannerStatus::JointFull
+        );
+    }
+
+    #[test]
+    fn test_validate_accepts_s_curve() {
+        // Shift sideways between two parallel headings
+        let mut plan = RailPlanner::new(Vec3::ZERO);
+        plan.start_forward = Vec3::NEG_X;
+        plan.end = vec3(40., 0., 5.);
+        plan.end_forward = PathRotationMode::Straight
+            .compute_end_forward(plan.start_forward, plan.end.normalize(), 0.)
+            .into();
+
+        assert_eq!(plan.end_forward, Vec3::X);
+        assert_eq!(
+            plan.validate(&[], &RailClass::default()),
+            RailPlannerStatus::Valid
+        );
+    }
+
+    #[test]
+    fn test_validate_rejects_shallow_branch() {
+        let (a, b) = (Entity::from_raw(1), Entity::from_raw(2));
+        let mut rail_a = Rail::from_joints(Vec3::ZERO, Vec3::NEG_X, Vec3::X * 20., Vec3::X);
+        let mut rail_b = Rail::from_joints(Vec3::X * 20., Vec3::NEG_X, Vec3::X * 40., Vec3::X);
+        rail_a.joints[RAIL_END_JOINT].n_joints[0] = Some(RailPathJointRef {
+            rail_entity: b,
+            joint_idx: RAIL_START_JOINT,
+        });
+        rail_b.joints[RAIL_START_JOINT].n_joints[0] = Some(RailPathJointRef {
+            rail_entity: a,
+            joint_idx: RAIL_END_JOINT,
+        });
+
+        // Branch off the end of a, almost in the same direction as b
+        let mut plan = RailPlanner::new(Vec3::X * 20.);
+        plan.start_forward = Vec3::NEG_X;
+        plan.start_joint = Some(RailPathJointRef {
+            rail_entity: a,
+            joint_idx: RAIL_END_JOINT,
+        });
+        plan.end = vec3(40., 0., 2.);
+        plan.end_forward = PathRotationMode::Curve
+            .compute_end_forward(plan.start_forward, (plan.end - plan.start).normalize(), 0.)
+            .into();
+
+        let rails = [(a, &rail_a), (b, &rail_b)];
+        assert!(matches!(
+            plan.validate(&rails, &RailClass::default()),
+            RailPlannerStatus::CurveTooShallow(_)
+        ));
+        let lenient = RailClass {
+            min_radians: 0.,
+            ..default()
+        };
+        assert!(!matches!(
+            plan.validate(&rails, &lenient),
+            RailPlannerStatus::CurveTooShallow(_)
+        ));
+    }
+
+    #[test]
+    fn test_build_rails_through_needs_two_points() {
+        let mut world = World::new();
+        assert!(build_rails_through(&mut world, &[]).unwrap().is_empty());
+        assert!(build_rails_through(&mut world, &[Vec3::ZERO])
+            .unwrap()
+            .is_empty());
+        assert_eq!(rail_count(&mut world), 0);
+    }
+
+    #[test]
+    fn test_build_rails_through_rejects_invalid_segment() {
+        let mut world = World::new();
+        let points = [Vec3::ZERO, vec3(20., 0., 0.), vec3(21., 0., 0.)];
+
+        let err = build_rails_through(&mut world, &points).unwrap_err();
+        assert!(matches!(
+            err,
+            RailBuildError::InvalidSegment {
+                segment: 1,
+                status: RailPlannerStatus::RailTooShort(..)
+            }
+        ));
+        assert_eq!(rail_count(&mut world), 0);
+    }
+
+    #[test]
+    fn test_build_rails_through_respects_rail_limit() {
+        let mut world = World::new();
+        world.insert_resource(RailLimit { max_rails: Some(2) });
+        let points = [Vec3::ZERO, vec3(20., 0., 0.), vec3(40., 0., 0.)];
+        build_rails_through(&mut world, &points).unwrap();
+
+        let err = build_rails_through(&mut world, &[points[2], vec3(60., 0., 0.)]).unwrap_err();
+        assert_eq!(err, RailBuildError::RailLimitReached(2));
+        assert_eq!(rail_count(&mut world), 2);
+    }
+}
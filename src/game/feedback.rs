@@ -0,0 +1,172 @@
+//! Feedback to the player about their actions
+use super::*;
+use bevy::audio::Pitch;
+use std::collections::VecDeque;
+use std::time::Duration;
+
+pub(super) fn feedback_plugin(app: &mut App) {
+    app.add_event::<FeedbackEvent>();
+    app.init_resource::<FeedbackLog>();
+    app.init_resource::<AudioFeedbackSettings>();
+    app.register_type::<AudioFeedbackSettings>();
+    app.add_systems(
+        Startup,
+        (load_audio_feedback_assets, spawn_feedback_status_line),
+    );
+    app.add_systems(
+        Update,
+        (
+            toggle_audio_feedback,
+            update_feedback_status_line,
+            record_feedback_log.run_if(on_event::<FeedbackEvent>),
+            play_error_sound.run_if(on_event::<FeedbackEvent>),
+        ),
+    );
+}
+
+/// Feedback about a player action, such as a build being rejected
+#[derive(Event, Clone)]
+pub struct FeedbackEvent {
+    pub message: String,
+    pub is_error: bool,
+}
+
+impl FeedbackEvent {
+    pub fn info(message: impl Into<String>) -> Self {
+        FeedbackEvent {
+            message: message.into(),
+            is_error: false,
+        }
+    }
+
+    pub fn error(message: impl Into<String>) -> Self {
+        FeedbackEvent {
+            message: message.into(),
+            is_error: true,
+        }
+    }
+}
+
+/// History of feedback, kept around after the feedback is no longer shown
+#[derive(Resource)]
+pub struct FeedbackLog {
+    pub entries: VecDeque<FeedbackLogEntry>,
+    pub capacity: usize,
+}
+
+impl Default for FeedbackLog {
+    fn default() -> Self {
+        const DEFAULT_CAPACITY: usize = 100;
+        FeedbackLog {
+            entries: VecDeque::with_capacity(DEFAULT_CAPACITY),
+            capacity: DEFAULT_CAPACITY,
+        }
+    }
+}
+
+pub struct FeedbackLogEntry {
+    // Elapsed time in seconds when the feedback was sent
+    pub time: f32,
+    pub event: FeedbackEvent,
+}
+
+#[derive(Resource)]
+pub struct AudioFeedbackAssets {
+    error: Handle<Pitch>,
+}
+
+#[derive(Resource, Reflect, Default)]
+#[reflect(Resource)]
+pub struct AudioFeedbackSettings {
+    pub muted: bool,
+}
+
+fn load_audio_feedback_assets(mut c: Commands, mut pitches: ResMut<Assets<Pitch>>) {
+    c.insert_resource(AudioFeedbackAssets {
+        error: pitches.add(Pitch::new(220.0, Duration::from_millis(120))),
+    });
+}
+
+fn toggle_audio_feedback(
+    mut settings: ResMut<AudioFeedbackSettings>,
+    q: Query<&ActionState<PlayerBuildAction>, With<NetOwner>>,
+) {
+    if q.iter()
+        .any(|input| input.just_pressed(&PlayerBuildAction::ToggleAudioFeedback))
+    {
+        settings.muted = !settings.muted;
+    }
+}
+
+/// Shows the latest feedback at the bottom of the screen for a short while
+#[derive(Component)]
+#[require(Text, Node)]
+pub struct FeedbackStatusLine {
+    timer: Timer,
+}
+
+fn spawn_feedback_status_line(mut c: Commands) {
+    const STATUS_LINE_DURATION: Duration = Duration::from_secs(2);
+    let mut timer = Timer::new(STATUS_LINE_DURATION, TimerMode::Once);
+    timer.tick(STATUS_LINE_DURATION);
+    c.spawn((
+        FeedbackStatusLine { timer },
+        Node {
+            position_type: PositionType::Absolute,
+            bottom: Val::Px(16.),
+            width: Val::Percent(100.),
+            justify_content: JustifyContent::Center,
+            ..default()
+        },
+        TextLayout::new_with_justify(JustifyText::Center),
+    ));
+}
+
+fn update_feedback_status_line(
+    mut q: Query<(&mut FeedbackStatusLine, &mut Text, &mut TextColor)>,
+    mut ev_feedback: EventReader<FeedbackEvent>,
+    time: Res<Time>,
+) {
+    let latest = ev_feedback.read().last();
+    q.iter_mut().for_each(|(mut status, mut text, mut color)| {
+        if let Some(ev) = latest {
+            text.0 = ev.message.clone();
+            color.0 = if ev.is_error {
+                Color::srgb(1.0, 0.4, 0.4)
+            } else {
+                Color::WHITE
+            };
+            status.timer.reset();
+        } else if status.timer.tick(time.delta()).just_finished() {
+            text.0.clear();
+        }
+    });
+}
+
+fn record_feedback_log(
+    mut log: ResMut<FeedbackLog>,
+    mut ev_feedback: EventReader<FeedbackEvent>,
+    time: Res<Time>,
+) {
+    for ev in ev_feedback.read() {
+        while log.entries.len() >= log.capacity.max(1) {
+            log.entries.pop_front();
+        }
+        log.entries.push_back(FeedbackLogEntry {
+            time: time.elapsed_secs(),
+            event: ev.clone(),
+        });
+    }
+}
+
+fn play_error_sound(
+    mut c: Commands,
+    mut ev_feedback: EventReader<FeedbackEvent>,
+    assets: Res<AudioFeedbackAssets>,
+    settings: Res<AudioFeedbackSettings>,
+) {
+    // Play a single sound, even if multiple errors happened this frame
+    if ev_feedback.read().any(|ev| ev.is_error) && !settings.muted {
+        c.spawn((AudioPlayer(assets.error.clone()), PlaybackSettings::DESPAWN));
+    }
+}
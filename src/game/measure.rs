@@ -0,0 +1,101 @@
+//! Ruler tool to measure distances in the world while building
+use super::*;
+use building::rail::rail_planner::RailPlanner;
+
+pub(super) fn measure_plugin(app: &mut App) {
+    app.add_systems(
+        Update,
+        (
+            (toggle_measurement, update_measurement)
+                .chain()
+                .run_if(any_with_component::<InputContext<PlayerBuildAction>>),
+            destroy_measurement,
+        ),
+    );
+}
+
+#[derive(Component, Default)]
+#[require(Text, Node)]
+pub struct Measurement {
+    // Point we measure from, None until the first interact
+    pub anchor: Option<Vec3>,
+}
+
+fn toggle_measurement(
+    mut c: Commands,
+    q: Query<Entity, (With<Measurement>, With<NetOwner>)>,
+    planners: Query<Entity, (With<RailPlanner>, With<NetOwner>)>,
+    input: Query<&ActionState<PlayerBuildAction>, With<NetOwner>>,
+) {
+    let input = input.single();
+    if !input.just_pressed(&PlayerBuildAction::Measure) {
+        return;
+    }
+
+    if q.is_empty() {
+        // We can't build while measuring
+        planners.iter().for_each(|e| c.entity(e).despawn());
+        c.spawn((Measurement::default(), NetOwner));
+    } else {
+        q.iter().for_each(|e| c.entity(e).despawn());
+    }
+}
+
+fn update_measurement(
+    mut gizmos: Gizmos,
+    mut q: Query<(&mut Measurement, &mut Text, &mut Node), With<NetOwner>>,
+    player_state: Query<(&PlayerCursor, &ActionState<PlayerBuildAction>), With<NetOwner>>,
+) {
+    let (cursor, input) = player_state.single();
+
+    q.iter_mut()
+        .for_each(|(mut measurement, mut text, mut node)| {
+            if input.just_pressed(&PlayerBuildAction::Interact) {
+                measurement.anchor = match measurement.anchor {
+                    Some(_) => None,
+                    None => Some(cursor.build_pos),
+                };
+            }
+
+            text.0 = match measurement.anchor {
+                Some(anchor) => {
+                    let delta = cursor.build_pos - anchor;
+                    let run = delta.xz().length();
+                    let grade = if run > 0.0 {
+                        delta.y / run * 100.0
+                    } else {
+                        0.0
+                    };
+
+                    gizmos.line(anchor, cursor.build_pos, Color::srgb(1.0, 0.8, 0.1));
+                    gizmos.sphere(
+                        Isometry3d::from_translation(anchor),
+                        0.25,
+                        Color::srgb(1.0, 0.8, 0.1),
+                    );
+
+                    format!("Distance {:.2} Grade {:.1}%", delta.length(), grade)
+                }
+                None => "Measure: click to place anchor".into(),
+            };
+
+            if let Some(pos) = cursor.screen_pos {
+                node.left = Val::Px(pos.x);
+                node.top = Val::Px(pos.y - 32.);
+            }
+        });
+}
+
+fn destroy_measurement(
+    mut c: Commands,
+    q: Query<Entity, (With<Measurement>, With<NetOwner>)>,
+    mut event: EventReader<PlayerStateChangedEvent>,
+) {
+    for e in event.read() {
+        if e.new_state == PlayerState::Viewing && e.old_state == PlayerState::Building {
+            q.into_iter().for_each(|e| {
+                c.entity(e).despawn();
+            });
+        }
+    }
+}
@@ -1,18 +1,29 @@
 //! Systems related to player actions
 use super::*;
+use std::error::Error;
+use std::path::Path;
 
 pub(super) fn player_plugin(app: &mut App) {
     app.add_plugins(InputContextPlugin::<PlayerViewAction>::default());
     app.add_plugins(InputContextPlugin::<PlayerBuildAction>::default());
-    app.add_event::<PlayerStateEvent>();
+    app.add_event::<PlayerStateChangedEvent>();
+    app.add_systems(
+        Update,
+        save_build_preferences.run_if(|options: Res<LaunchOptions>| options.persist_preferences),
+    );
 }
 
+pub const BUILD_PREFERENCES_PATH: &str = "settings.ron";
+
 #[derive(
     Actionlike, PartialEq, Eq, Hash, Clone, Copy, Debug, Reflect, PartialOrd, Ord, DisplayDebug,
 )]
 pub enum PlayerViewAction {
     EnterBuildMode,
     ExitGame,
+    Screenshot,
+    SaveGame,
+    LoadGame,
 }
 
 impl InputContextlike for PlayerViewAction {
@@ -20,6 +31,9 @@ fn default_input_map() -> InputMap<Self> {
         InputMap::default()
             .with(PlayerViewAction::EnterBuildMode, MouseButton::Left)
             .with(PlayerViewAction::ExitGame, KeyCode::Escape)
+            .with(PlayerViewAction::Screenshot, KeyCode::F12)
+            .with(PlayerViewAction::SaveGame, KeyCode::F5)
+            .with(PlayerViewAction::LoadGame, KeyCode::F9)
     }
 
     fn group_name() -> String {
@@ -37,7 +51,16 @@ pub enum PlayerBuildAction {
     SnapRotate,
     SnapCounterRotate,
     CyclePathRotateMode,
+    CycleRailClass,
+    SnapToNearestHeading,
     ToggleSnapToGrid,
+    ToggleSnapRotation,
+    Measure,
+    #[actionlike(Axis)]
+    AdjustBuildHeight,
+    Undo,
+    Redo,
+    ToggleAudioFeedback,
 }
 
 impl InputContextlike for PlayerBuildAction {
@@ -64,7 +87,24 @@ fn default_input_map() -> InputMap<Self> {
                     .with(KeyCode::KeyR),
             )
             .with(PlayerBuildAction::CyclePathRotateMode, KeyCode::Tab)
-            .with(PlayerBuildAction::ToggleSnapToGrid, KeyCode::ControlLeft)
+            .with(PlayerBuildAction::CycleRailClass, KeyCode::KeyC)
+            .with(PlayerBuildAction::SnapToNearestHeading, KeyCode::KeyH)
+            .with(PlayerBuildAction::ToggleSnapToGrid, KeyCode::KeyG)
+            .with(PlayerBuildAction::ToggleSnapRotation, KeyCode::KeyT)
+            .with(PlayerBuildAction::Measure, KeyCode::KeyM)
+            .with_axis(
+                PlayerBuildAction::AdjustBuildHeight,
+                AxislikeChord::new(ModifierKey::Alt, MouseScrollAxis::Y.digital()),
+            )
+            .with(
+                PlayerBuildAction::Undo,
+                ButtonlikeChord::modified(ModifierKey::Control, KeyCode::KeyZ),
+            )
+            .with(
+                PlayerBuildAction::Redo,
+                ButtonlikeChord::modified(ModifierKey::Control, KeyCode::KeyY),
+            )
+            .with(PlayerBuildAction::ToggleAudioFeedback, KeyCode::KeyN)
     }
     fn group_name() -> String {
         "Build Actions".into()
@@ -90,7 +130,16 @@ pub enum PlayerState {
 }
 
 impl PlayerState {
+    /// Swaps the input contexts and sends a [`PlayerStateChangedEvent`] if the state changes
     pub fn set(&mut self, new_state: PlayerState, c: &mut Commands, e: Entity) {
+        if *self == new_state {
+            return;
+        }
+        c.send_event(PlayerStateChangedEvent {
+            old_state: self.clone(),
+            new_state: new_state.clone(),
+        });
+
         match self {
             PlayerState::Viewing => c.entity(e).remove::<InputContext<PlayerViewAction>>(),
             PlayerState::Building => c.entity(e).remove::<InputContext<PlayerBuildAction>>(),
@@ -116,7 +165,7 @@ pub fn in_player_state(
 }
 
 #[derive(Event)]
-pub struct PlayerStateEvent {
+pub struct PlayerStateChangedEvent {
     pub new_state: PlayerState,
     pub old_state: PlayerState,
 }
@@ -126,8 +175,13 @@ pub struct PlayerStateEvent {
 pub struct PlayerCursor {
     pub screen_pos: Option<Vec2>,
     pub should_snap_to_grid: bool,
+    pub should_snap_rotation: bool,
+    // Align the end of a rail with the closest rail
+    pub should_snap_to_nearest_heading: bool,
     // Cached build rotation
     pub manual_rotation: f32,
+    // Height of the plane we build on
+    pub build_height: f32,
     pub rotation_mode: PathRotationMode,
     // Can be world or grid pos based on user desire
     pub build_pos: Vec3,
@@ -135,3 +189,117 @@ pub struct PlayerCursor {
     pub prev_world_pos: Vec3,
     pub world_grid_pos: Vec3,
 }
+
+/// Build habits of the player that we keep between sessions
+#[derive(Serialize, Deserialize, Default, PartialEq, Clone, Debug)]
+#[serde(default)]
+pub struct BuildPreferences {
+    pub should_snap_to_grid: bool,
+    pub should_snap_rotation: bool,
+    pub should_snap_to_nearest_heading: bool,
+    pub rotation_mode: PathRotationMode,
+}
+
+impl BuildPreferences {
+    pub fn from_cursor(cursor: &PlayerCursor) -> Self {
+        BuildPreferences {
+            should_snap_to_grid: cursor.should_snap_to_grid,
+            should_snap_rotation: cursor.should_snap_rotation,
+            should_snap_to_nearest_heading: cursor.should_snap_to_nearest_heading,
+            rotation_mode: cursor.rotation_mode.clone(),
+        }
+    }
+
+    pub fn apply(&self, cursor: &mut PlayerCursor) {
+        cursor.should_snap_to_grid = self.should_snap_to_grid;
+        cursor.should_snap_rotation = self.should_snap_rotation;
+        cursor.should_snap_to_nearest_heading = self.should_snap_to_nearest_heading;
+        cursor.rotation_mode = self.rotation_mode.clone();
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), Box<dyn Error>> {
+        let data = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())?;
+        std::fs::write(path, data)?;
+        Ok(())
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Box<dyn Error>> {
+        let data = std::fs::read_to_string(path)?;
+        Ok(ron::from_str(&data)?)
+    }
+}
+
+/// Cursor with the preferences of the last session, or the defaults if there are none
+pub fn load_player_cursor() -> PlayerCursor {
+    let mut cursor = PlayerCursor::default();
+    if std::fs::exists(BUILD_PREFERENCES_PATH).unwrap_or(false) {
+        match BuildPreferences::load(BUILD_PREFERENCES_PATH) {
+            Ok(preferences) => preferences.apply(&mut cursor),
+            Err(err) => warn!("Failed to load {BUILD_PREFERENCES_PATH}, using defaults: {err}"),
+        }
+    }
+    cursor
+}
+
+fn save_build_preferences(
+    q: Query<&PlayerCursor, (With<NetOwner>, Changed<PlayerCursor>)>,
+    mut last: Local<Option<BuildPreferences>>,
+) {
+    let Ok(cursor) = q.get_single() else {
+        return;
+    };
+
+    let preferences = BuildPreferences::from_cursor(cursor);
+    // The first value we see is what we loaded, no need to write it back
+    if last.as_ref().is_some_and(|last| *last != preferences) {
+        if let Err(err) = preferences.save(BUILD_PREFERENCES_PATH) {
+            warn!("Failed to save {BUILD_PREFERENCES_PATH}: {err}");
+        }
+    }
+    *last = Some(preferences);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::ecs::system::RunSystemOnce;
+
+    #[test]
+    fn test_build_preferences_roundtrip() {
+        let preferences = BuildPreferences {
+            should_snap_to_grid: true,
+            rotation_mode: PathRotationMode::Chase,
+            ..default()
+        };
+        let data = ron::to_string(&preferences).unwrap();
+        let loaded = ron::from_str::<BuildPreferences>(&data).unwrap();
+
+        let mut cursor = PlayerCursor::default();
+        loaded.apply(&mut cursor);
+        assert_eq!(BuildPreferences::from_cursor(&cursor), preferences);
+    }
+
+    #[test]
+    fn test_entering_build_mode_sends_one_event() {
+        let mut app = crate::headless_test_app();
+        app.update();
+
+        app.world_mut()
+            .run_system_once(
+                |mut c: Commands, mut q: Query<(Entity, &mut PlayerState), With<NetOwner>>| {
+                    let (e, mut state) = q.single_mut();
+                    state.set(PlayerState::Building, &mut c, e);
+                    // Setting the same state again should not send anything
+                    state.set(PlayerState::Building, &mut c, e);
+                },
+            )
+            .unwrap();
+
+        let events = app.world().resource::<Events<PlayerStateChangedEvent>>();
+        let mut cursor = events.get_cursor();
+        let sent: Vec<_> = cursor.read(events).collect();
+        assert_eq!(sent.len(), 1);
+        assert!(sent[0].old_state == PlayerState::Viewing);
+        assert!(sent[0].new_state == PlayerState::Building);
+    }
+}
@@ -0,0 +1,443 @@
+//! Saving and loading of the world
+use super::*;
+use bevy::utils::HashMap;
+use building::history::BuildHistory;
+use building::rail::{Rail, RailLimit, RailPathJointRef, RAIL_CURVES_MAX};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::path::Path;
+
+pub const SAVE_PATH: &str = "world.ron";
+
+pub(super) fn save_plugin(app: &mut App) {
+    app.add_systems(
+        Update,
+        (save_world, load_world).run_if(any_with_component::<InputContext<PlayerViewAction>>),
+    );
+}
+
+/// Everything we need to rebuild the world
+#[derive(Serialize, Deserialize, Default, PartialEq, Debug)]
+pub struct WorldSnapshot {
+    pub rails: Vec<RailSnapshot>,
+    pub camera: Option<CameraSnapshot>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct RailSnapshot {
+    pub joints: [RailJointSnapshot; 2],
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct RailJointSnapshot {
+    pub pos: Vec3,
+    pub forward: Vec3,
+    // Connected joints as (rail index, joint index), entities are not stable between sessions
+    pub neighbors: Vec<(usize, usize)>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct CameraSnapshot {
+    pub center: Vec3,
+    pub zoom: f32,
+    pub pitch: f32,
+    pub yaw: f32,
+}
+
+/// Returned when a snapshot refers to data that doesn't exist, which happens with corrupted or hand edited files
+#[derive(Debug, PartialEq)]
+pub enum InvalidSnapshot {
+    ZeroLengthRail(usize),
+    // Rail index and the (rail index, joint index) neighbor it refers to
+    InvalidNeighbor(usize, (usize, usize)),
+    // Rail index and joint index of a joint with more neighbors than a joint can hold
+    TooManyNeighbors(usize, usize),
+    // (rail index, joint index) of a joint and a neighbor that doesn't connect back to it
+    OneWayNeighbor((usize, usize), (usize, usize)),
+}
+
+impl fmt::Display for InvalidSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InvalidSnapshot::ZeroLengthRail(rail) => write!(f, "Rail {rail} has no length"),
+            InvalidSnapshot::InvalidNeighbor(rail, (n_rail, n_joint)) => write!(
+                f,
+                "Rail {rail} connects to joint {n_joint} of rail {n_rail}, which doesn't exist"
+            ),
+            InvalidSnapshot::TooManyNeighbors(rail, joint) => write!(
+                f,
+                "Joint {joint} of rail {rail} has more than {RAIL_CURVES_MAX} neighbors"
+            ),
+            InvalidSnapshot::OneWayNeighbor((rail, joint), (n_rail, n_joint)) => write!(
+                f,
+                "Joint {joint} of rail {rail} connects to joint {n_joint} of rail {n_rail}, which doesn't connect back"
+            ),
+        }
+    }
+}
+
+impl Error for InvalidSnapshot {}
+
+impl WorldSnapshot {
+    pub fn capture<'a>(
+        rails: impl Iterator<Item = (Entity, &'a Rail)>,
+        camera: Option<&PanOrbitCamera>,
+    ) -> Self {
+        // Sort so saving the same world results in the same file
+        let mut rails: Vec<_> = rails.collect();
+        rails.sort_by_key(|(e, _)| *e);
+        let indices: HashMap<Entity, usize> = rails
+            .iter()
+            .enumerate()
+            .map(|(i, (e, _))| (*e, i))
+            .collect();
+
+        WorldSnapshot {
+            rails: rails
+                .iter()
+                .map(|(_, rail)| RailSnapshot {
+                    joints: rail.joints.each_ref().map(|joint| RailJointSnapshot {
+                        pos: joint.pos,
+                        forward: joint.forward,
+                        neighbors: joint
+                            .n_joints
+                            .iter()
+                            .flatten()
+                            .filter_map(|n_joint| {
+                                indices
+                                    .get(&n_joint.rail_entity)
+                                    .map(|i| (*i, n_joint.joint_idx))
+                            })
+                            .collect(),
+                    }),
+                })
+                .collect(),
+            camera: camera.map(|camera| CameraSnapshot {
+                center: camera.center,
+                zoom: camera.zoom,
+                pitch: camera.pitch,
+                yaw: camera.yaw,
+            }),
+        }
+    }
+
+    /// Check that all rails have length and that joints only connect to joints in this snapshot that connect back
+    pub fn validate(&self) -> Result<(), InvalidSnapshot> {
+        self.rails.iter().enumerate().try_for_each(|(i, rail)| {
+            let [start, end] = &rail.joints;
+            if start.pos.distance_squared(end.pos) <= f32::EPSILON {
+                return Err(InvalidSnapshot::ZeroLengthRail(i));
+            }
+
+            rail.joints
+                .iter()
+                .flat_map(|joint| joint.neighbors.iter())
+                .find(|(rail_idx, joint_idx)| {
+                    *rail_idx >= self.rails.len() || *joint_idx >= rail.joints.len()
+                })
+                .map_or(Ok(()), |neighbor| {
+                    Err(InvalidSnapshot::InvalidNeighbor(i, *neighbor))
+                })
+        })?;
+
+        // All neighbors exist, so we can look them up
+        self.rails
+            .iter()
+            .enumerate()
+            .flat_map(|(i, rail)| {
+                rail.joints
+                    .iter()
+                    .enumerate()
+                    .map(move |(j, joint)| ((i, j), joint))
+            })
+            .try_for_each(|(id, joint)| {
+                if joint.neighbors.len() > RAIL_CURVES_MAX {
+                    return Err(InvalidSnapshot::TooManyNeighbors(id.0, id.1));
+                }
+                joint
+                    .neighbors
+                    .iter()
+                    .find(|(n_rail, n_joint)| {
+                        !self.rails[*n_rail].joints[*n_joint].neighbors.contains(&id)
+                    })
+                    .map_or(Ok(()), |neighbor| {
+                        Err(InvalidSnapshot::OneWayNeighbor(id, *neighbor))
+                    })
+            })
+    }
+
+    /// Spawn the rails of this snapshot, returns the spawned entities in the same order
+    /// Nothing is spawned if the snapshot is invalid
+    pub fn spawn_rails(&self, c: &mut Commands) -> Result<Vec<Entity>, InvalidSnapshot> {
+        self.validate()?;
+
+        let entities: Vec<Entity> = self.rails.iter().map(|_| c.spawn_empty().id()).collect();
+
+        self.rails.iter().zip(&entities).for_each(|(snapshot, e)| {
+            let [start, end] = &snapshot.joints;
+            let mut rail = Rail::from_joints(start.pos, start.forward, end.pos, end.forward);
+
+            rail.joints
+                .iter_mut()
+                .zip(&snapshot.joints)
+                .for_each(|(joint, joint_snapshot)| {
+                    let neighbors =
+                        joint_snapshot
+                            .neighbors
+                            .iter()
+                            .filter_map(|(rail_idx, joint_idx)| {
+                                entities.get(*rail_idx).map(|rail_entity| RailPathJointRef {
+                                    rail_entity: *rail_entity,
+                                    joint_idx: *joint_idx,
+                                })
+                            });
+                    joint
+                        .n_joints
+                        .iter_mut()
+                        .zip(neighbors)
+                        .for_each(|(n_joint, neighbor)| *n_joint = Some(neighbor));
+                });
+
+            c.entity(*e).insert(rail);
+        });
+
+        Ok(entities)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), Box<dyn Error>> {
+        let data = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())?;
+        std::fs::write(path, data)?;
+        Ok(())
+    }
+
+    /// Load a snapshot, files that parse but refer to missing rails or joints are rejected as well
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Box<dyn Error>> {
+        let data = std::fs::read_to_string(path)?;
+        let snapshot: WorldSnapshot = ron::from_str(&data)?;
+        snapshot.validate()?;
+        Ok(snapshot)
+    }
+}
+
+impl CameraSnapshot {
+    pub fn apply(&self, camera: &mut PanOrbitCamera) {
+        camera.center = self.center;
+        camera.zoom = self.zoom;
+        camera.pitch = self.pitch;
+        camera.yaw = self.yaw;
+    }
+}
+
+fn save_world(
+    q: Query<&ActionState<PlayerViewAction>, With<NetOwner>>,
+    rails: Query<(Entity, &Rail)>,
+    cameras: Query<&PanOrbitCamera, With<NetOwner>>,
+    mut ev_feedback: EventWriter<FeedbackEvent>,
+) {
+    if !q
+        .iter()
+        .any(|input| input.just_pressed(&PlayerViewAction::SaveGame))
+    {
+        return;
+    }
+
+    let snapshot = WorldSnapshot::capture(rails.iter(), cameras.get_single().ok());
+    ev_feedback.send(match snapshot.save(SAVE_PATH) {
+        Ok(()) => FeedbackEvent::info(format!("Saved world to {SAVE_PATH}")),
+        Err(err) => FeedbackEvent::error(format!("Failed to save world: {err}")),
+    });
+}
+
+fn load_world(
+    mut c: Commands,
+    q: Query<&ActionState<PlayerViewAction>, With<NetOwner>>,
+    rails: Query<Entity, With<Rail>>,
+    mut cameras: Query<&mut PanOrbitCamera, With<NetOwner>>,
+    mut history: ResMut<BuildHistory>,
+    limit: Res<RailLimit>,
+    mut ev_feedback: EventWriter<FeedbackEvent>,
+) {
+    if !q
+        .iter()
+        .any(|input| input.just_pressed(&PlayerViewAction::LoadGame))
+    {
+        return;
+    }
+
+    // Loading validates, so a bad file doesn't leave us with an empty world
+    let snapshot = match WorldSnapshot::load(SAVE_PATH) {
+        Ok(snapshot) => snapshot,
+        Err(err) => {
+            ev_feedback.send(FeedbackEvent::error(format!("Failed to load world: {err}")));
+            return;
+        }
+    };
+    if !limit.allows(snapshot.rails.len()) {
+        ev_feedback.send(FeedbackEvent::error(format!(
+            "Failed to load world: {} rails exceeds the rail limit",
+            snapshot.rails.len()
+        )));
+        return;
+    }
+
+    rails.iter().for_each(|e| c.entity(e).despawn());
+    // History refers to the entities we just removed
+    history.clear();
+    // Validated by load, so this can't fail
+    let _ = snapshot.spawn_rails(&mut c);
+    if let (Some(camera), Ok(mut state)) = (&snapshot.camera, cameras.get_single_mut()) {
+        camera.apply(&mut state);
+    }
+
+    ev_feedback.send(FeedbackEvent::info(format!(
+        "Loaded world from {SAVE_PATH}"
+    )));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshot_roundtrip() {
+        let joint = |pos: Vec3, forward: Vec3, neighbors| RailJointSnapshot {
+            pos,
+            forward,
+            neighbors,
+        };
+        let snapshot = WorldSnapshot {
+            rails: vec![
+                RailSnapshot {
+                    joints: [
+                        joint(Vec3::ZERO, Vec3::NEG_X, vec![]),
+                        joint(Vec3::X * 10.0, Vec3::X, vec![(1, 0)]),
+                    ],
+                },
+                RailSnapshot {
+                    joints: [
+                        joint(Vec3::X * 10.0, Vec3::NEG_X, vec![(0, 1)]),
+                        joint(Vec3::X * 20.0, Vec3::X, vec![]),
+                    ],
+                },
+            ],
+            camera: Some(CameraSnapshot {
+                center: Vec3::new(1.0, 0.0, 2.0),
+                zoom: 0.5,
+                pitch: -0.5,
+                yaw: 1.0,
+            }),
+        };
+
+        let data = ron::to_string(&snapshot).unwrap();
+        assert_eq!(ron::from_str::<WorldSnapshot>(&data).unwrap(), snapshot);
+        assert_eq!(snapshot.validate(), Ok(()));
+    }
+
+    #[test]
+    fn test_load_rejects_invalid_neighbors() {
+        let path = std::env::temp_dir().join("project_odyssey_invalid_snapshot.ron");
+        let rail = RailSnapshot {
+            joints: [
+                RailJointSnapshot {
+                    pos: Vec3::ZERO,
+                    forward: Vec3::NEG_X,
+                    neighbors: vec![(5, 0)],
+                },
+                RailJointSnapshot {
+                    pos: Vec3::X * 10.0,
+                    forward: Vec3::X,
+                    neighbors: vec![],
+                },
+            ],
+        };
+        let data = ron::to_string(&WorldSnapshot {
+            rails: vec![rail],
+            camera: None,
+        })
+        .unwrap();
+        std::fs::write(&path, data).unwrap();
+
+        assert!(WorldSnapshot::load(&path).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_snapshot_rejects_invalid_data() {
+        let joint = |pos: Vec3, neighbors| RailJointSnapshot {
+            pos,
+            forward: Vec3::X,
+            neighbors,
+        };
+        let snapshot = |joints| WorldSnapshot {
+            rails: vec![RailSnapshot { joints }],
+            camera: None,
+        };
+
+        assert_eq!(
+            snapshot([joint(Vec3::ZERO, vec![]), joint(Vec3::ZERO, vec![])]).validate(),
+            Err(InvalidSnapshot::ZeroLengthRail(0))
+        );
+        assert_eq!(
+            snapshot([joint(Vec3::ZERO, vec![(0, 2)]), joint(Vec3::X, vec![])]).validate(),
+            Err(InvalidSnapshot::InvalidNeighbor(0, (0, 2)))
+        );
+        assert_eq!(
+            snapshot([joint(Vec3::ZERO, vec![]), joint(Vec3::X, vec![(1, 0)])]).validate(),
+            Err(InvalidSnapshot::InvalidNeighbor(0, (1, 0)))
+        );
+    }
+
+    #[test]
+    fn test_snapshot_rejects_invalid_links() {
+        let rail = |x: f32, start, end| RailSnapshot {
+            joints: [
+                RailJointSnapshot {
+                    pos: Vec3::X * x,
+                    forward: Vec3::NEG_X,
+                    neighbors: start,
+                },
+                RailJointSnapshot {
+                    pos: Vec3::X * (x + 20.),
+                    forward: Vec3::X,
+                    neighbors: end,
+                },
+            ],
+        };
+        let snapshot = |rails| WorldSnapshot {
+            rails,
+            camera: None,
+        };
+
+        // Both rails agree they are connected
+        assert_eq!(
+            snapshot(vec![
+                rail(0., vec![], vec![(1, 0)]),
+                rail(20., vec![(0, 1)], vec![])
+            ])
+            .validate(),
+            Ok(())
+        );
+        // Rail 1 doesn't connect back
+        assert_eq!(
+            snapshot(vec![
+                rail(0., vec![], vec![(1, 0)]),
+                rail(20., vec![], vec![])
+            ])
+            .validate(),
+            Err(InvalidSnapshot::OneWayNeighbor((0, 1), (1, 0)))
+        );
+        // A joint can't hold more neighbors than it has curves
+        let mut rails: Vec<_> = (0..=RAIL_CURVES_MAX)
+            .map(|_| rail(20., vec![(RAIL_CURVES_MAX + 1, 1)], vec![]))
+            .collect();
+        rails.push(rail(
+            0.,
+            vec![],
+            (0..=RAIL_CURVES_MAX).map(|i| (i, 0)).collect(),
+        ));
+        assert_eq!(
+            snapshot(rails).validate(),
+            Err(InvalidSnapshot::TooManyNeighbors(RAIL_CURVES_MAX + 1, 1))
+        );
+    }
+}
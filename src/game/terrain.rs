@@ -0,0 +1,40 @@
+//! Ground height queries, so building doesn't depend on a flat world
+use super::*;
+
+pub(super) fn terrain_plugin(app: &mut App) {
+    app.init_resource::<Terrain>();
+}
+
+/// Implement this for anything that can tell us how high the ground is
+pub trait TerrainHeight: Send + Sync + 'static {
+    fn height_at(&self, x: f32, z: f32) -> f32;
+}
+
+/// Ground that sits at y 0 everywhere
+pub struct FlatTerrain;
+
+impl TerrainHeight for FlatTerrain {
+    fn height_at(&self, _x: f32, _z: f32) -> f32 {
+        0.0
+    }
+}
+
+/// The terrain that is used to place things on, swap the source to use a different terrain
+#[derive(Resource)]
+pub struct Terrain {
+    pub source: Box<dyn TerrainHeight>,
+}
+
+impl Default for Terrain {
+    fn default() -> Self {
+        Terrain {
+            source: Box::new(FlatTerrain),
+        }
+    }
+}
+
+impl Terrain {
+    pub fn height_at(&self, x: f32, z: f32) -> f32 {
+        self.source.height_at(x, z)
+    }
+}
@@ -0,0 +1,185 @@
+//! Day/night cycle that drives the sun and the fog
+use super::*;
+use bevy::pbr::light_consts::lux::AMBIENT_DAYLIGHT;
+use std::f32::consts::TAU;
+
+pub(super) fn time_of_day_plugin(app: &mut App) {
+    app.add_plugins(InputContextPlugin::<TimeOfDayAction>::default());
+    app.register_type::<TimeOfDay>();
+    app.add_systems(
+        Update,
+        (
+            start_time_of_day.run_if(not(resource_exists::<TimeOfDay>)),
+            (
+                update_time_of_day,
+                update_sun.run_if(resource_changed::<TimeOfDay>),
+            )
+                .chain()
+                .run_if(resource_exists::<TimeOfDay>),
+        ),
+    );
+}
+
+pub const SUN_DAY_COLOR: Color = Color::srgb(0.98, 0.95, 0.82);
+const SUN_HORIZON_COLOR: Color = Color::srgb(1.0, 0.55, 0.3);
+pub const FOG_DAY_COLOR: Color = Color::srgba(0.35, 0.48, 0.66, 1.0);
+const FOG_NIGHT_COLOR: Color = Color::srgba(0.03, 0.04, 0.09, 1.0);
+
+// Highest point of the sun at noon, we don't go fully overhead to keep some shadow direction
+const SUN_MAX_ELEVATION: f32 = 60.0 * PI / 180.0;
+// Fraction of daylight illuminance we keep at night so the world doesn't go pitch black
+const NIGHT_ILLUMINANCE: f32 = 0.05;
+// In game hours per second while scrubbing
+const SCRUB_SPEED: f32 = 4.0;
+
+#[derive(
+    Actionlike, PartialEq, Eq, Hash, Clone, Copy, Debug, Reflect, PartialOrd, Ord, DisplayDebug,
+)]
+pub enum TimeOfDayAction {
+    TogglePause,
+    #[actionlike(Axis)]
+    Scrub,
+}
+
+impl InputContextlike for TimeOfDayAction {
+    fn default_input_map() -> InputMap<Self> {
+        InputMap::default()
+            .with(TimeOfDayAction::TogglePause, KeyCode::Backslash)
+            .with_axis(
+                TimeOfDayAction::Scrub,
+                VirtualAxis::new(KeyCode::BracketLeft, KeyCode::BracketRight),
+            )
+    }
+
+    fn group_name() -> String {
+        "Time of Day Actions".into()
+    }
+}
+
+/// Only inserted once the player controls the time, remove this resource to keep the sun static
+#[derive(Resource, Reflect)]
+#[reflect(Resource)]
+pub struct TimeOfDay {
+    // In range [0, 24)
+    pub hours: f32,
+    // In game hours per second
+    pub speed: f32,
+    pub paused: bool,
+}
+
+impl Default for TimeOfDay {
+    fn default() -> Self {
+        Self {
+            hours: 12.0,
+            speed: 0.5,
+            paused: true,
+        }
+    }
+}
+
+impl TimeOfDay {
+    /// Angle of the sun above the horizon, negative at night
+    pub fn sun_elevation(&self) -> f32 {
+        ((self.hours - 6.0) / 12.0 * PI).sin() * SUN_MAX_ELEVATION
+    }
+
+    /// How much daylight there is, 0 at night and 1 at noon
+    pub fn daylight(&self) -> f32 {
+        (self.sun_elevation() / SUN_MAX_ELEVATION).max(0.0)
+    }
+}
+
+/// Marks the directional light that follows the time of day
+#[derive(Component)]
+#[require(InputContext<TimeOfDayAction>)]
+pub struct Sun;
+
+// The spawned sun keeps its hand-tuned direction until the player asks for the day cycle
+fn start_time_of_day(mut c: Commands, input: Query<&ActionState<TimeOfDayAction>, With<Sun>>) {
+    let Ok(input) = input.get_single() else {
+        return;
+    };
+
+    let toggled = input.just_pressed(&TimeOfDayAction::TogglePause);
+    if toggled || input.value(&TimeOfDayAction::Scrub) != 0.0 {
+        c.insert_resource(TimeOfDay {
+            paused: !toggled,
+            ..default()
+        });
+    }
+}
+
+fn update_time_of_day(
+    mut time_of_day: ResMut<TimeOfDay>,
+    time: Res<Time>,
+    input: Query<&ActionState<TimeOfDayAction>, With<Sun>>,
+) {
+    let mut delta_hours = 0.0;
+    if let Ok(input) = input.get_single() {
+        if input.just_pressed(&TimeOfDayAction::TogglePause) {
+            time_of_day.paused = !time_of_day.paused;
+        }
+        delta_hours += input.value(&TimeOfDayAction::Scrub) * SCRUB_SPEED * time.delta_secs();
+    }
+
+    if !time_of_day.paused {
+        delta_hours += time_of_day.speed * time.delta_secs();
+    }
+
+    if delta_hours != 0.0 {
+        time_of_day.hours = (time_of_day.hours + delta_hours).rem_euclid(24.0);
+    }
+}
+
+fn update_sun(
+    time_of_day: Res<TimeOfDay>,
+    mut suns: Query<(&mut Transform, &mut DirectionalLight), With<Sun>>,
+    mut fogs: Query<&mut DistanceFog>,
+) {
+    let elevation = time_of_day.sun_elevation();
+    let daylight = time_of_day.daylight();
+    // Sun rises in the east and sets in the west over a full day
+    let azimuth = time_of_day.hours / 24.0 * TAU;
+    let to_sun = Vec3::new(
+        elevation.cos() * azimuth.sin(),
+        elevation.sin(),
+        elevation.cos() * azimuth.cos(),
+    );
+    let sun_color = SUN_HORIZON_COLOR.mix(&SUN_DAY_COLOR, daylight);
+
+    suns.iter_mut().for_each(|(mut transform, mut light)| {
+        *transform = Transform::IDENTITY.looking_to(-to_sun, Vec3::Y);
+        light.color = sun_color;
+        light.illuminance = AMBIENT_DAYLIGHT * daylight.max(NIGHT_ILLUMINANCE);
+    });
+
+    fogs.iter_mut().for_each(|mut fog| {
+        fog.color = FOG_NIGHT_COLOR.mix(&FOG_DAY_COLOR, daylight);
+        fog.directional_light_color = sun_color.with_alpha(0.5 * daylight);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sun_keeps_spawn_direction_until_requested() {
+        let mut app = crate::headless_test_app();
+        app.update();
+        let world = app.world_mut();
+        let spawned = *world
+            .query_filtered::<&Transform, With<Sun>>()
+            .single(world);
+
+        app.update();
+        let world = app.world_mut();
+        assert!(!world.contains_resource::<TimeOfDay>());
+        assert_eq!(
+            *world
+                .query_filtered::<&Transform, With<Sun>>()
+                .single(world),
+            spawned
+        );
+    }
+}
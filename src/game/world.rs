@@ -1,36 +1,184 @@
 //! Loads our initial world
 use super::*;
 use bevy::pbr::{CascadeShadowConfigBuilder, DirectionalLightShadowMap, NotShadowCaster};
+use rail::RailLimit;
 
 pub(super) fn world_plugin(app: &mut App) {
     app.insert_resource(DirectionalLightShadowMap { size: 4096 });
-    app.add_systems(Startup, spawn_test_world);
+    app.add_systems(Startup, (spawn_test_world, load_initial_layout).chain());
+    app.add_systems(
+        Update,
+        apply_fog_settings.run_if(resource_changed::<FogSettings>),
+    );
+    app.init_resource::<LaunchOptions>();
+    app.init_resource::<FogSettings>();
+    app.register_type::<FogSettings>();
+}
+
+#[derive(Resource, Reflect)]
+#[reflect(Resource)]
+pub struct FogSettings {
+    pub enabled: bool,
+    // Distance in world units up to which objects retain visibility (>= 5% contrast)
+    pub visibility: f32,
+}
+
+impl Default for FogSettings {
+    fn default() -> Self {
+        FogSettings {
+            enabled: true,
+            visibility: PanOrbitCameraSettings::default().max_radius * 5.0,
+        }
+    }
+}
+
+impl FogSettings {
+    fn falloff(&self) -> FogFalloff {
+        FogFalloff::from_visibility_colors(
+            self.visibility,
+            Color::srgb(0.35, 0.5, 0.66), // atmospheric extinction color (after light is lost due to absorption by atmospheric particles)
+            Color::srgb(0.8, 0.844, 1.0), // atmospheric inscattering color (light gained due to scattering from the sun)
+        )
+    }
+}
+
+fn apply_fog_settings(
+    mut c: Commands,
+    settings: Res<FogSettings>,
+    mut cameras: Query<(Entity, Option<&mut DistanceFog>), With<PanOrbitCamera>>,
+    time_of_day: Option<ResMut<TimeOfDay>>,
+) {
+    let mut inserted = false;
+    cameras
+        .iter_mut()
+        .for_each(|(e, fog)| match (settings.enabled, fog) {
+            (true, Some(mut fog)) => fog.falloff = settings.falloff(),
+            (true, None) => {
+                c.entity(e).insert(DistanceFog {
+                    color: FOG_DAY_COLOR,
+                    directional_light_color: Color::srgba(1.0, 0.95, 0.85, 0.5),
+                    directional_light_exponent: 30.0,
+                    falloff: settings.falloff(),
+                });
+                inserted = true;
+            }
+            (false, Some(_)) => {
+                c.entity(e).remove::<DistanceFog>();
+            }
+            (false, None) => {}
+        });
+
+    // Let the time of day tint the new fog
+    if let (true, Some(mut time_of_day)) = (inserted, time_of_day) {
+        time_of_day.set_changed();
+    }
+}
+
+/// Environment variable to point at a layout to load on startup, `--layout <path>` can be used as well
+pub const LAYOUT_ENV_VAR: &str = "ODYSSEY_LAYOUT";
+
+/// Environment variable with a seed for a reproducible scenario, `--seed <u64>` can be used as well
+pub const SEED_ENV_VAR: &str = "ODYSSEY_SEED";
+
+/// Startup input from outside the app, the default reads and writes nothing so tests stay isolated
+#[derive(Resource, Default)]
+pub struct LaunchOptions {
+    // Layout to load on startup
+    pub layout: Option<String>,
+    // Seed for a reproducible scenario
+    pub seed: Option<u64>,
+    // Load and save build preferences from BUILD_PREFERENCES_PATH
+    pub persist_preferences: bool,
+}
+
+impl LaunchOptions {
+    /// Options passed with `--layout <path>` and `--seed <u64>` or their env vars
+    pub fn from_env() -> Self {
+        Self {
+            layout: launch_arg("--layout", LAYOUT_ENV_VAR),
+            seed: launch_arg("--seed", SEED_ENV_VAR).and_then(|seed| {
+                seed.parse()
+                    .inspect_err(|err| warn!("Ignoring invalid seed {seed}: {err}"))
+                    .ok()
+            }),
+            persist_preferences: true,
+        }
+    }
+}
+
+fn launch_arg(flag: &str, env_var: &str) -> Option<String> {
+    let mut args = std::env::args().skip_while(|arg| arg != flag).skip(1);
+    args.next().or_else(|| std::env::var(env_var).ok())
+}
+
+/// Transforms of the test blocks, scattered around the origin when seeded or in a line otherwise
+fn block_transforms(seed: Option<u64>, len: f32) -> Vec<Transform> {
+    const BLOCKS: usize = 10;
+    // Half the size of the area seeded blocks are scattered in
+    const SCATTER_EXTENT: f32 = 50.0;
+
+    match seed {
+        Some(seed) => {
+            let mut rng = fastrand::Rng::with_seed(seed);
+            let mut scatter = || (rng.f32() * 2.0 - 1.0) * SCATTER_EXTENT;
+            (0..BLOCKS)
+                .map(|_| {
+                    Transform::from_xyz(scatter(), len * 0.5, scatter())
+                        .with_rotation(Quat::from_rotation_y(scatter() / SCATTER_EXTENT * PI))
+                })
+                .collect()
+        }
+        None => (0..BLOCKS)
+            .map(|i| Transform::from_translation(Vec3::new(len * 2.0 * i as f32, len * 0.5, 0.0)))
+            .collect(),
+    }
+}
+
+fn load_initial_layout(
+    mut c: Commands,
+    mut cameras: Query<&mut PanOrbitCamera, With<NetOwner>>,
+    limit: Res<RailLimit>,
+    options: Res<LaunchOptions>,
+) {
+    let Some(path) = &options.layout else {
+        return;
+    };
+
+    match WorldSnapshot::load(path).and_then(|snapshot| {
+        if !limit.allows(snapshot.rails.len()) {
+            return Err(format!("{} rails exceeds the rail limit", snapshot.rails.len()).into());
+        }
+        snapshot.spawn_rails(&mut c)?;
+        Ok(snapshot)
+    }) {
+        Ok(snapshot) => {
+            if let (Some(camera), Ok(mut state)) = (&snapshot.camera, cameras.get_single_mut()) {
+                camera.apply(&mut state);
+            }
+            info!("Loaded initial layout from {path}");
+        }
+        Err(err) => warn!("Failed to load initial layout from {path}, using empty world: {err}"),
+    }
 }
 
 fn spawn_test_world(
     mut c: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
+    options: Res<LaunchOptions>,
 ) {
     // --- Gameplay
     // Player State
-    c.spawn((NetOwner, PlayerState::default()));
+    let cursor = if options.persist_preferences {
+        load_player_cursor()
+    } else {
+        PlayerCursor::default()
+    };
+    c.spawn((NetOwner, PlayerState::default(), cursor));
 
     // Camera
-    c.spawn((
-        NetOwner,
-        PanOrbitCamera::default(),
-        DistanceFog {
-            color: Color::srgba(0.35, 0.48, 0.66, 1.0),
-            directional_light_color: Color::srgba(1.0, 0.95, 0.85, 0.5),
-            directional_light_exponent: 30.0,
-            falloff: FogFalloff::from_visibility_colors(
-                PanOrbitCameraSettings::default().max_radius * 5.0, // distance in world units up to which objects retain visibility (>= 5% contrast)
-                Color::srgb(0.35, 0.5, 0.66), // atmospheric extinction color (after light is lost due to absorption by atmospheric particles)
-                Color::srgb(0.8, 0.844, 1.0), // atmospheric inscattering color (light gained due to scattering from the sun)
-            ),
-        },
-    ));
+    // Fog is added by apply_fog_settings
+    c.spawn((NetOwner, PanOrbitCamera::default()));
 
     // Sun
     let cascade_shadow_config = CascadeShadowConfigBuilder {
@@ -46,8 +194,9 @@ fn spawn_test_world(
     .build();
 
     c.spawn((
+        Sun,
         DirectionalLight {
-            color: Color::srgb(0.98, 0.95, 0.82),
+            color: SUN_DAY_COLOR,
             shadows_enabled: true,
             ..default()
         },
@@ -78,11 +227,36 @@ fn spawn_test_world(
     const LEN: f32 = 2.0;
     let mesh = meshes.add(Cuboid::from_length(LEN));
     let material = materials.add(Color::BLACK);
-    for i in 0..10 {
+    let seed = options.seed;
+    if let Some(seed) = seed {
+        info!("Scattering blocks with seed {seed}");
+    }
+    for transform in block_transforms(seed, LEN) {
         c.spawn((
             Mesh3d(mesh.clone()),
             MeshMaterial3d(material.clone()),
-            Transform::from_translation(Vec3::new(LEN * 2.0 * i as f32, LEN * 0.5, 0.0)),
+            transform,
         ));
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seeded_blocks_are_reproducible() {
+        assert_eq!(
+            block_transforms(Some(42), 2.0),
+            block_transforms(Some(42), 2.0)
+        );
+        assert_ne!(
+            block_transforms(Some(42), 2.0),
+            block_transforms(Some(7), 2.0)
+        );
+        assert_eq!(
+            block_transforms(None, 2.0)[1].translation,
+            Vec3::new(4.0, 1.0, 0.0)
+        );
+    }
+}
@@ -182,6 +182,25 @@ pub struct AllInputContextEntries {
     pub entries: Vec<InputContextEntries>,
 }
 
+impl AllInputContextEntries {
+    /// Find the entry of an action in any group
+    ///
+    /// Actions are matched by comparing their Display output, so two contexts with an identically
+    /// displayed action will return the first one that was collected
+    pub fn get_input_entry(&self, action: &impl fmt::Display) -> Option<&InputContextEntry> {
+        let action = action.to_string();
+        self.entries
+            .iter()
+            .flat_map(|group| group.entries.iter())
+            .find(|entry| entry.action == action)
+    }
+
+    /// Find a group by its [`InputContextlike::group_name`]
+    pub fn get_group(&self, name: &str) -> Option<&InputContextEntries> {
+        self.entries.iter().find(|group| group.name == name)
+    }
+}
+
 pub struct InputContextEntries {
     pub name: String,
     /// Entries are ordered according to InputConfig Ord impl
@@ -395,3 +414,66 @@ fn update_input_display_text(
     });
     info!("-- Updating input display data DONE");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(
+        Actionlike, PartialEq, Eq, Hash, Clone, Copy, Debug, Reflect, PartialOrd, Ord, DisplayDebug,
+    )]
+    enum TestAction {
+        Jump,
+        Crouch,
+        Unbound,
+    }
+
+    impl InputContextlike for TestAction {
+        fn default_input_map() -> InputMap<Self> {
+            InputMap::default()
+                .with(TestAction::Jump, KeyCode::Space)
+                .with(TestAction::Crouch, KeyCode::KeyC)
+                .with(
+                    TestAction::Crouch,
+                    ButtonlikeChord::new([KeyCode::ShiftLeft, KeyCode::KeyX]),
+                )
+        }
+
+        fn group_name() -> String {
+            "Test Actions".into()
+        }
+    }
+
+    fn test_entries() -> AllInputContextEntries {
+        AllInputContextEntries {
+            entries: vec![InputContextEntries::new(
+                &InputContext::<TestAction>::default(),
+                &TestAction::default_input_map(),
+            )],
+        }
+    }
+
+    #[test]
+    fn test_get_input_entry() {
+        let entries = test_entries();
+
+        assert_eq!(
+            entries.get_input_entry(&TestAction::Jump).unwrap().input,
+            "Space"
+        );
+        assert_eq!(
+            entries.get_input_entry(&TestAction::Crouch).unwrap().input,
+            "C | Shift + X"
+        );
+        assert!(entries.get_input_entry(&TestAction::Unbound).is_none());
+    }
+
+    #[test]
+    fn test_get_group() {
+        let entries = test_entries();
+
+        let group = entries.get_group("Test Actions").unwrap();
+        assert_eq!(group.entries.len(), 2);
+        assert!(entries.get_group("Missing Actions").is_none());
+    }
+}
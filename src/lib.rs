@@ -11,6 +11,8 @@
 
 impl Plugin for AppPlugin {
     fn build(&self, app: &mut App) {
+        // Only the real app reads the command line and touches files on disk
+        app.insert_resource(game::world::LaunchOptions::from_env());
         app.add_plugins((
             DefaultPlugins.set(
                 // here we configure the main window
@@ -29,3 +31,55 @@ fn build(&self, app: &mut App) {
         ));
     }
 }
+
+/// App with the gameplay plugins but without a window or rendering, so we can step the simulation in tests
+pub fn headless_test_app() -> App {
+    let mut app = App::new();
+    app.add_plugins((
+        MinimalPlugins,
+        AssetPlugin::default(),
+        TransformPlugin,
+        HierarchyPlugin,
+        bevy::input::InputPlugin,
+    ));
+    // Gizmos are still drawn but there is nothing to render them
+    app.init_asset::<Shader>();
+    app.add_plugins(bevy::gizmos::GizmoPlugin);
+    app.init_asset::<Mesh>();
+    app.init_asset::<StandardMaterial>();
+    app.init_asset::<Pitch>();
+    app.add_plugins((
+        debug::debug_gizmo_plugin,
+        camera::CameraPlugin,
+        game::GamePlugin,
+    ));
+    app
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use game::building::rail::{rail_planner::build_rails_through, Rail};
+
+    #[test]
+    fn test_headless_app_builds_rails() {
+        let mut app = headless_test_app();
+        app.update();
+
+        let points = [Vec3::ZERO, Vec3::X * 20.0, Vec3::X * 40.0];
+        build_rails_through(app.world_mut(), &points).unwrap();
+        app.update();
+
+        let world = app.world_mut();
+        assert_eq!(world.query::<&Rail>().iter(world).count(), 2);
+    }
+
+    #[test]
+    fn test_headless_app_ignores_launch_environment() {
+        let app = headless_test_app();
+        let options = app.world().resource::<game::world::LaunchOptions>();
+        assert!(options.layout.is_none());
+        assert!(options.seed.is_none());
+        assert!(!options.persist_preferences);
+    }
+}